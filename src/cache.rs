@@ -0,0 +1,245 @@
+// Terafirma static site generator
+//   Penn Bauman <me@pennbauman.com>
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use crate::page::PageBuilder;
+use crate::recursive_ls;
+
+// Template tags that refer to other templates
+const TEMPLATE_TAGS: [&str; 3] = ["extends", "include", "import"];
+// Template functions which read the site model
+const SITE_FUNCTIONS: [&str; 2] = ["get_page", "get_section"];
+// Template functions with results that cannot be tracked, pages calling them are rendered by every build
+const UNTRACKED_FUNCTIONS: [&str; 5] = ["now", "get_env", "get_random", "load_data", "asset_exists"];
+static ALWAYS: &str = "always";
+
+
+// Record of the previous build, used to skip unchanged outputs
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    version: String,
+    output_dir: PathBuf,
+    #[serde(rename = "static")]
+    statics: BTreeMap<String, StaticEntry>,
+    outputs: BTreeMap<String, OutputEntry>,
+}
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StaticEntry {
+    size: u64,
+    modified: u128,
+}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutputEntry {
+    hash: String,
+    dependencies: Vec<String>,
+}
+impl Manifest {
+    pub fn new(output_dir: &Path) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            output_dir: output_dir.to_path_buf(),
+            ..Default::default()
+        }
+    }
+    // Load the manifest for a previous build of the same output directory, if there is one
+    pub fn load(file: &Path, output_dir: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(file).ok()?;
+        let manifest: Self = serde_json::from_str(&contents).ok()?;
+        if manifest.version != env!("CARGO_PKG_VERSION") || manifest.output_dir != output_dir || !output_dir.is_dir() {
+            return None;
+        }
+        Some(manifest)
+    }
+    pub fn save(&self, file: &Path) -> Result<()> {
+        fs::write(file, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+    // Record a static file, returning whether it changed since the previous build
+    pub fn add_static(&mut self, previous: Option<&Self>, name: &str, source: &Path) -> Result<bool> {
+        let meta = fs::metadata(source)?;
+        let modified = meta.modified()?.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+        let entry = StaticEntry { size: meta.len(), modified };
+        let changed = previous.and_then(|m| m.statics.get(name)) != Some(&entry);
+        self.statics.insert(name.to_string(), entry);
+        Ok(changed)
+    }
+    // Record an output, returning whether it changed since the previous build
+    pub fn add_output(&mut self, previous: Option<&Self>, path: &str, hash: u64, dependencies: Vec<String>) -> bool {
+        let hash = format!("{:016x}", hash);
+        let changed = previous.and_then(|m| m.outputs.get(path)).map(|o| &o.hash) != Some(&hash)
+            || dependencies.iter().any(|d| d == ALWAYS);
+        self.outputs.insert(path.to_string(), OutputEntry { hash, dependencies });
+        changed
+    }
+    pub fn output_count(&self) -> usize {
+        self.outputs.len()
+    }
+    // Outputs of the previous build that are no longer produced
+    pub fn removed<'a>(&self, previous: &'a Self) -> Vec<&'a str> {
+        previous.statics.keys()
+            .chain(previous.outputs.keys())
+            .filter(|p| !self.statics.contains_key(*p) && !self.outputs.contains_key(*p))
+            .map(|p| p.as_str())
+            .collect()
+    }
+}
+
+
+#[derive(Debug, Default)]
+struct TemplateInfo {
+    hash: u64,
+    refs: Vec<String>,
+    uses_site: bool,
+    uses_data: bool,
+    untracked: bool,
+}
+
+// Determines what each output depends on and fingerprints those dependencies
+#[derive(Debug, Default)]
+pub struct Dependencies {
+    templates: HashMap<String, TemplateInfo>,
+    global_hash: u64,
+    site_hash: u64,
+    data_hash: u64,
+}
+impl Dependencies {
+    pub fn new(template_dir: &Path, data_dir: &Path, global: &str, site: &str) -> Result<Self> {
+        let mut templates = HashMap::new();
+        for name in recursive_ls(template_dir)? {
+            let source = fs::read_to_string(template_dir.join(&name)).unwrap_or_default();
+            templates.insert(name, TemplateInfo {
+                hash: hash(&source),
+                refs: template_refs(&source),
+                uses_site: uses_site(&source),
+                uses_data: uses_name(&source, "data"),
+                untracked: calls_untracked(&source),
+            });
+        }
+        let mut data_files = recursive_ls(data_dir)?;
+        data_files.sort();
+        let mut hasher = DefaultHasher::new();
+        for f in data_files {
+            f.hash(&mut hasher);
+            fs::read(data_dir.join(&f))?.hash(&mut hasher);
+        }
+        Ok(Self {
+            templates,
+            global_hash: hash(global),
+            site_hash: hash(site),
+            data_hash: hasher.finish(),
+        })
+    }
+    // Fingerprint a page, along with a description of its dependencies
+    pub fn page(&self, page: &PageBuilder, default_template: Option<&str>) -> (u64, Vec<String>) {
+        let mut dependencies = vec![];
+        let mut hasher = DefaultHasher::new();
+        format!("{:?}", page).hash(&mut hasher);
+        match page {
+            PageBuilder::Redirect{ .. } => {
+                dependencies.push("config:redirect".to_string());
+                return (hasher.finish(), dependencies);
+            },
+            PageBuilder::FileBody{ file, .. } => dependencies.push(format!("file:{}", file)),
+            PageBuilder::TemplateBody{ .. } => (),
+            _ => dependencies.push("config:page".to_string()),
+        }
+        dependencies.push("config:context".to_string());
+        self.global_hash.hash(&mut hasher);
+        default_template.hash(&mut hasher);
+
+        // Find all templates used by the page
+        let body = page.body().unwrap_or_default();
+        let mut uses_site = uses_site(body);
+        let mut uses_data = uses_name(body, "data");
+        let mut untracked = calls_untracked(body);
+        let mut pending = template_refs(body);
+        match page.template() {
+            Some(t) if !t.is_empty() => pending.push(t.to_string()),
+            Some(_) => (),
+            None => pending.extend(default_template.map(|t| t.to_string())),
+        }
+        let mut used = BTreeSet::new();
+        while let Some(name) = pending.pop() {
+            if !used.insert(name.clone()) {
+                continue;
+            }
+            if let Some(info) = self.templates.get(&name) {
+                uses_site |= info.uses_site;
+                uses_data |= info.uses_data;
+                untracked |= info.untracked;
+                pending.extend(info.refs.iter().cloned());
+            }
+        }
+        for name in used {
+            self.templates.get(&name).map(|t| t.hash).hash(&mut hasher);
+            dependencies.push(format!("template:{}", name));
+        }
+        if uses_site {
+            self.site_hash.hash(&mut hasher);
+            dependencies.push("site".to_string());
+        }
+        if uses_data {
+            self.data_hash.hash(&mut hasher);
+            dependencies.push("data".to_string());
+        }
+        if untracked {
+            dependencies.push(ALWAYS.to_string());
+        }
+        (hasher.finish(), dependencies)
+    }
+}
+
+
+pub fn hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Find the names of templates referenced by extends, include or import tags
+fn template_refs(source: &str) -> Vec<String> {
+    let mut refs = vec![];
+    for tag in source.split("{%").skip(1) {
+        let tag = tag.split("%}").next().unwrap_or_default();
+        let tag = tag.trim_start_matches('-').trim_start();
+        if TEMPLATE_TAGS.iter().any(|t| tag.starts_with(t)) {
+            // Every other part is a quoted string
+            refs.extend(tag.split(['"', '\'', '`']).skip(1).step_by(2).map(|s| s.to_string()));
+        }
+    }
+    refs
+}
+
+fn uses_site(source: &str) -> bool {
+    uses_name(source, "site") || SITE_FUNCTIONS.iter().any(|f| calls_function(source, f))
+}
+
+fn calls_untracked(source: &str) -> bool {
+    UNTRACKED_FUNCTIONS.iter().any(|f| calls_function(source, f))
+}
+
+// Check if a template might call a function, its name followed by '('
+fn calls_function(source: &str, name: &str) -> bool {
+    source.match_indices(name).any(|(i, _)| {
+        let before = source[..i].chars().next_back();
+        !before.is_some_and(|c| c.is_alphanumeric() || c == '_') && source[i + name.len()..].trim_start().starts_with('(')
+    })
+}
+
+// Check if a name might be referred to by a template, the whole context counts as all names
+fn uses_name(source: &str, name: &str) -> bool {
+    if source.contains("__tera_context") {
+        return true;
+    }
+    source.match_indices(name).any(|(i, _)| {
+        let before = source[..i].chars().next_back();
+        let after = source[i + name.len()..].chars().next();
+        !before.is_some_and(|c| c.is_alphanumeric() || c == '_') && !after.is_some_and(|c| c.is_alphanumeric() || c == '_')
+    })
+}
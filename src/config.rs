@@ -0,0 +1,127 @@
+// Terafirma static site generator
+//   Penn Bauman <me@pennbauman.com>
+use std::path::PathBuf;
+use serde::Deserialize;
+use crate::page::Format;
+
+
+// Contents of a Terafirma.toml file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub settings: Settings,
+    #[serde(default)]
+    pub context: toml::Table,
+    #[serde(default)]
+    pub page: Vec<PageConfig>,
+    #[serde(default)]
+    pub redirect: Vec<RedirectConfig>,
+    #[serde(default)]
+    pub taxonomy: Vec<TaxonomyConfig>,
+    #[serde(default)]
+    pub generate: Vec<GenerateConfig>,
+    pub sitemap: Option<SitemapConfig>,
+    #[serde(default)]
+    pub feed: Vec<FeedConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub output_dir: Option<PathBuf>,
+    pub template_dir: Option<PathBuf>,
+    pub static_dir: Option<PathBuf>,
+    pub page_dir: Option<PathBuf>,
+    pub data_dir: Option<PathBuf>,
+    pub default_template: Option<String>,
+    pub base_url: Option<String>,
+    #[serde(default)]
+    pub override_static: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageConfig {
+    pub path: String,
+    #[serde(default)]
+    pub context: toml::Table,
+    pub body: Option<String>,
+    #[serde(default)]
+    pub format: Format,
+    pub template: Option<String>,
+    pub paginate: Option<PaginateConfig>,
+    // Any other keys are added to the page's context
+    #[serde(flatten)]
+    pub extra: toml::Table,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PaginateConfig {
+    pub section: String,
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
+    pub sort_by: Option<String>,
+    #[serde(default)]
+    pub reverse: bool,
+}
+fn default_per_page() -> usize {
+    10
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedirectConfig {
+    pub path: String,
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TaxonomyConfig {
+    pub name: String,
+    pub path: Option<String>,
+    pub term_template: String,
+    pub list_template: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenerateConfig {
+    pub path: String,
+    pub template: String,
+    pub data: Option<Vec<toml::Value>>,
+    pub source: Option<PathBuf>,
+    #[serde(default)]
+    pub context: toml::Table,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SitemapConfig {
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeedConfig {
+    pub title: String,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub section: Option<String>,
+    pub pages: Option<String>,
+    #[serde(default = "default_feed_sort")]
+    pub sort_by: String,
+    #[serde(default = "default_feed_reverse")]
+    pub reverse: bool,
+    pub limit: Option<usize>,
+    pub rss: Option<String>,
+    pub atom: Option<String>,
+    pub json: Option<String>,
+}
+fn default_feed_sort() -> String {
+    "date".to_string()
+}
+fn default_feed_reverse() -> bool {
+    true
+}
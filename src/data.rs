@@ -0,0 +1,116 @@
+// Terafirma static site generator
+//   Penn Bauman <me@pennbauman.com>
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use anyhow::{bail, Context as _, Result};
+use tera::{Tera, Map, Value};
+use crate::{recursive_ls, toml_to_tera};
+
+
+// Load every data file in a directory, nested by their relative paths
+pub fn load_dir(dir: &Path) -> Result<Value> {
+    let mut data = Map::new();
+    // Paths of the tables which came from files, so directories are not merged into them
+    let mut file_keys = HashSet::new();
+    let mut files = recursive_ls(dir)?;
+    files.sort();
+    for f in files {
+        let path = Path::new(&f);
+        if !is_data_file(path) {
+            continue;
+        }
+        let value = load_file(&dir.join(path))?;
+        // Find table for the file's directory
+        let mut table = &mut data;
+        if let Some(parent) = path.parent() {
+            let mut prefix = PathBuf::new();
+            for part in parent.iter() {
+                prefix.push(part);
+                if file_keys.contains(&prefix) {
+                    bail!("Data directory '{}' conflicts with a data file of the same name", prefix.display());
+                }
+                let key = part.to_string_lossy().to_string();
+                let entry = table.entry(key).or_insert_with(|| Value::Object(Map::new()));
+                table = match entry {
+                    Value::Object(m) => m,
+                    _ => bail!("Data file conflicts with directory '{}'", parent.display()),
+                };
+            }
+        }
+        let key = path.file_stem().unwrap().to_string_lossy().to_string();
+        if table.contains_key(&key) {
+            bail!("Data file '{}' conflicts with another data file or directory", f);
+        }
+        table.insert(key, value);
+        file_keys.insert(path.with_extension(""));
+    }
+    Ok(Value::Object(data))
+}
+
+// Parse a JSON, TOML, YAML or CSV file
+pub fn load_file(path: &Path) -> Result<Value> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read data file '{}'", path.display()))?;
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    let value = match ext {
+        "json" => serde_json::from_str(&contents).map_err(anyhow::Error::from),
+        "toml" => toml::from_str::<toml::Value>(&contents).map(|v| toml_to_tera(&v)).map_err(anyhow::Error::from),
+        "yaml" | "yml" => serde_yaml::from_str(&contents).map_err(anyhow::Error::from),
+        "csv" => parse_csv(&contents),
+        _ => bail!("Unknown data file type '{}'", path.display()),
+    };
+    value.with_context(|| format!("Failed to parse data file '{}'", path.display()))
+}
+
+pub fn is_data_file(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some("json" | "toml" | "yaml" | "yml" | "csv"))
+}
+
+// Register functions for reading files in the project
+pub fn register_functions(tera: &mut Tera, root_dir: &Path, static_dir: &Path) {
+    let root_dir = root_dir.to_path_buf();
+    tera.register_function("load_data", move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+        let file = get_file_arg("load_data", &root_dir, args)?;
+        load_file(&file).map_err(|e| tera::Error::msg(format!("{:#}", e)))
+    });
+    let static_dir = static_dir.to_path_buf();
+    tera.register_function("asset_exists", move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+        let file = get_file_arg("asset_exists", &static_dir, args)?;
+        Ok(Value::Bool(file.is_file()))
+    });
+}
+
+// File argument of a function, which must be within a directory
+fn get_file_arg(name: &str, dir: &Path, args: &HashMap<String, Value>) -> tera::Result<PathBuf> {
+    let path = match args.get("path") {
+        Some(Value::String(p)) => p.trim_start_matches('/'),
+        Some(_) => return Err(tera::Error::msg(format!("The `{}` function argument `path` must be a string", name))),
+        None => return Err(tera::Error::msg(format!("The `{}` function has to have a `path` argument", name))),
+    };
+    if !is_contained_path(Path::new(path)) {
+        return Err(tera::Error::msg(format!("Invalid path '{}' for the `{}` function", path, name)));
+    }
+    Ok(dir.join(path))
+}
+
+// Check a path stays within the directory it is joined to, so it has no root or '..'
+pub fn is_contained_path(path: &Path) -> bool {
+    path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+// Read CSV rows into a list of tables using the header row as keys
+fn parse_csv(contents: &str) -> Result<Value> {
+    let mut reader = csv::Reader::from_reader(contents.as_bytes());
+    let headers = reader.headers()?.clone();
+    let mut rows = vec![];
+    for record in reader.records() {
+        let record = record?;
+        let row: Map<String, Value> = headers.iter()
+            .zip(record.iter())
+            .map(|(h, v)| (h.to_string(), Value::String(v.to_string())))
+            .collect();
+        rows.push(Value::Object(row));
+    }
+    Ok(Value::Array(rows))
+}
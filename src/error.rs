@@ -0,0 +1,41 @@
+// Terafirma static site generator
+//   Penn Bauman <me@pennbauman.com>
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+
+// A page which failed to build, the underlying error is its source
+#[derive(Debug)]
+pub struct PageError {
+    // Output path, starting with '/'
+    pub path: String,
+    // Page file, template or configuration file the page comes from
+    pub file: PathBuf,
+    pub error: anyhow::Error,
+}
+impl fmt::Display for PageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to build page '{}'", self.path)
+    }
+}
+impl Error for PageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
+// Every page which failed to build, in path order
+#[derive(Debug)]
+pub struct BuildErrors {
+    pub pages: Vec<PageError>,
+}
+impl fmt::Display for BuildErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pages.len() {
+            1 => write!(f, "Failed to build 1 page"),
+            n => write!(f, "Failed to build {} pages", n),
+        }
+    }
+}
+impl Error for BuildErrors {}
@@ -0,0 +1,88 @@
+// Terafirma static site generator
+//   Penn Bauman <me@pennbauman.com>
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use anyhow::Result;
+use tera::{Filter, Function, Test, Tera, Value};
+
+type Hook = dyn Fn(&mut Tera) -> Result<()> + Send + Sync;
+
+
+// Filters, functions and testers added by programs using Terafirma as a library
+#[derive(Default)]
+pub struct Extensions {
+    filters: Vec<(String, Arc<dyn Filter>)>,
+    functions: Vec<(String, Arc<dyn Function>)>,
+    testers: Vec<(String, Arc<dyn Test>)>,
+    hooks: Vec<Box<Hook>>,
+}
+impl Extensions {
+    pub fn add_filter(&mut self, name: &str, filter: Arc<dyn Filter>) {
+        self.filters.push((name.to_string(), filter));
+    }
+    pub fn add_function(&mut self, name: &str, function: Arc<dyn Function>) {
+        self.functions.push((name.to_string(), function));
+    }
+    pub fn add_tester(&mut self, name: &str, tester: Arc<dyn Test>) {
+        self.testers.push((name.to_string(), tester));
+    }
+    pub fn add_hook(&mut self, hook: Box<Hook>) {
+        self.hooks.push(hook);
+    }
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty() && self.functions.is_empty() && self.testers.is_empty() && self.hooks.is_empty()
+    }
+    // Register everything with a Tera instance, in the order it was added, then run the hooks
+    pub fn apply(&self, tera: &mut Tera) -> Result<()> {
+        for (name, f) in &self.filters {
+            tera.register_filter(name, Shared(f.clone()));
+        }
+        for (name, f) in &self.functions {
+            tera.register_function(name, Shared(f.clone()));
+        }
+        for (name, t) in &self.testers {
+            tera.register_tester(name, Shared(t.clone()));
+        }
+        for hook in &self.hooks {
+            hook(tera)?;
+        }
+        Ok(())
+    }
+}
+// Closures cannot be printed, so only list names
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("filters", &self.filters.iter().map(|(n, _)| n).collect::<Vec<_>>())
+            .field("functions", &self.functions.iter().map(|(n, _)| n).collect::<Vec<_>>())
+            .field("testers", &self.testers.iter().map(|(n, _)| n).collect::<Vec<_>>())
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+
+// Shares one filter, function or tester between every Tera instance built
+struct Shared<T: ?Sized>(Arc<T>);
+impl Filter for Shared<dyn Filter> {
+    fn filter(&self, value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        self.0.filter(value, args)
+    }
+    fn is_safe(&self) -> bool {
+        self.0.is_safe()
+    }
+}
+impl Function for Shared<dyn Function> {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        self.0.call(args)
+    }
+    fn is_safe(&self) -> bool {
+        self.0.is_safe()
+    }
+}
+impl Test for Shared<dyn Test> {
+    fn test(&self, value: Option<&Value>, args: &[Value]) -> tera::Result<bool> {
+        self.0.test(value, args)
+    }
+}
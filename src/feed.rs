@@ -0,0 +1,225 @@
+// Terafirma static site generator
+//   Penn Bauman <me@pennbauman.com>
+use std::fmt::Write;
+use anyhow::{bail, Context as _, Result};
+use chrono::{DateTime, FixedOffset, SecondsFormat};
+use globset::{Glob, GlobMatcher};
+use serde_json::json;
+use tera::Value;
+use crate::config::FeedConfig;
+use crate::sitemap::xml_escape;
+use crate::{get_page_path, site};
+
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Rss,
+    Atom,
+    Json,
+}
+
+#[derive(Debug)]
+enum Selection {
+    Section(String),
+    Pages(GlobMatcher),
+}
+
+#[derive(Debug)]
+struct Item {
+    title: String,
+    url: String,
+    date: Option<DateTime<FixedOffset>>,
+    summary: Option<String>,
+}
+
+// Lists the newest pages of a section or matching a pattern, for feed readers
+#[derive(Debug)]
+pub struct Feed {
+    title: String,
+    description: Option<String>,
+    author: Option<String>,
+    selection: Selection,
+    sort_by: String,
+    reverse: bool,
+    limit: Option<usize>,
+    outputs: Vec<(Format, String)>,
+}
+impl Feed {
+    pub fn from_config(config: FeedConfig) -> Result<Self> {
+        let selection = match (config.section, config.pages) {
+            (Some(s), None) => Selection::Section(s),
+            (None, Some(p)) => Selection::Pages(Glob::new(p.trim_start_matches('/'))
+                .with_context(|| format!("Invalid pages pattern '{}'", p))?.compile_matcher()),
+            (Some(_), Some(_)) => bail!("Feed cannot have both a section and pages"),
+            (None, None) => bail!("Feed requires a section or pages"),
+        };
+        if config.atom.is_some() && config.author.is_none() {
+            bail!("Atom feeds require an author");
+        }
+        let mut outputs = vec![];
+        for (format, path) in [(Format::Rss, config.rss), (Format::Atom, config.atom), (Format::Json, config.json)] {
+            if let Some(p) = path {
+                outputs.push((format, get_page_path(&p)?));
+            }
+        }
+        if outputs.is_empty() {
+            bail!("Feed requires at least one of rss, atom or json");
+        }
+        Ok(Self {
+            title: config.title,
+            description: config.description,
+            author: config.author,
+            selection,
+            sort_by: config.sort_by,
+            reverse: config.reverse,
+            limit: config.limit,
+            outputs,
+        })
+    }
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+    // Paths and contents of the feed's files
+    pub fn files(&self, base_url: &str, site: &Value) -> Result<Vec<(String, String)>> {
+        let mut pages: Vec<Value> = match &self.selection {
+            Selection::Section(s) => match site["sections"].get(s) {
+                Some(section) => section["pages"].as_array().unwrap().clone(),
+                None => bail!("Unknown section '{}' for feed '{}'", s, self.title),
+            },
+            Selection::Pages(m) => site["pages"].as_array().unwrap().iter()
+                .filter(|p| m.is_match(p["path"].as_str().unwrap_or_default().trim_start_matches('/')))
+                .cloned()
+                .collect(),
+        };
+        site::sort_page_list(&mut pages, &self.sort_by, self.reverse);
+        if let Some(l) = self.limit {
+            pages.truncate(l);
+        }
+
+        let mut items = vec![];
+        for p in pages {
+            let url = site::absolute_url(base_url, p["url"].as_str().unwrap_or_default());
+            let date = match &p["date"] {
+                Value::Null => None,
+                Value::String(s) => match site::parse_date(s) {
+                    Some(d) => Some(d),
+                    None => bail!("Invalid date '{}' for page '{}' in feed '{}'", s, p["path"].as_str().unwrap_or_default(), self.title),
+                },
+                d => bail!("Invalid date '{}' for page '{}' in feed '{}'", d, p["path"].as_str().unwrap_or_default(), self.title),
+            };
+            let context = &p["context"];
+            let summary = context.get("summary").or(context.get("description")).and_then(|s| s.as_str());
+            items.push(Item {
+                title: p["title"].as_str().map(|t| t.to_string()).unwrap_or(url.clone()),
+                url,
+                date,
+                summary: summary.map(|s| s.to_string()),
+            });
+        }
+
+        let mut ret = vec![];
+        for (format, path) in &self.outputs {
+            let feed_url = site::absolute_url(base_url, path);
+            let contents = match format {
+                Format::Rss => self.rss(base_url, &feed_url, &items),
+                Format::Atom => self.atom(base_url, &feed_url, &items),
+                Format::Json => self.json(base_url, &feed_url, &items)?,
+            };
+            ret.push((path.clone(), contents));
+        }
+        Ok(ret)
+    }
+    fn rss(&self, base_url: &str, feed_url: &str, items: &[Item]) -> String {
+        let mut ret = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        ret.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n");
+        writeln!(ret, "\t<title>{}</title>", xml_escape(&self.title)).unwrap();
+        writeln!(ret, "\t<link>{}</link>", xml_escape(base_url)).unwrap();
+        writeln!(ret, "\t<description>{}</description>", xml_escape(self.description.as_ref().unwrap_or(&self.title))).unwrap();
+        writeln!(ret, "\t<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>", xml_escape(feed_url)).unwrap();
+        if let Some(d) = updated(items) {
+            writeln!(ret, "\t<lastBuildDate>{}</lastBuildDate>", d.to_rfc2822()).unwrap();
+        }
+        for i in items {
+            ret.push_str("\t<item>\n");
+            writeln!(ret, "\t\t<title>{}</title>", xml_escape(&i.title)).unwrap();
+            writeln!(ret, "\t\t<link>{}</link>", xml_escape(&i.url)).unwrap();
+            writeln!(ret, "\t\t<guid>{}</guid>", xml_escape(&i.url)).unwrap();
+            if let Some(d) = i.date {
+                writeln!(ret, "\t\t<pubDate>{}</pubDate>", d.to_rfc2822()).unwrap();
+            }
+            if let Some(s) = &i.summary {
+                writeln!(ret, "\t\t<description>{}</description>", xml_escape(s)).unwrap();
+            }
+            ret.push_str("\t</item>\n");
+        }
+        ret.push_str("</channel>\n</rss>\n");
+        ret
+    }
+    fn atom(&self, base_url: &str, feed_url: &str, items: &[Item]) -> String {
+        // Atom requires an update time, feeds without dates use the Unix epoch so builds are repeatable
+        let updated = updated(items).unwrap_or(DateTime::UNIX_EPOCH.fixed_offset());
+        let mut ret = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        writeln!(ret, "\t<title>{}</title>", xml_escape(&self.title)).unwrap();
+        if let Some(d) = &self.description {
+            writeln!(ret, "\t<subtitle>{}</subtitle>", xml_escape(d)).unwrap();
+        }
+        writeln!(ret, "\t<link href=\"{}\"/>", xml_escape(base_url)).unwrap();
+        writeln!(ret, "\t<link href=\"{}\" rel=\"self\"/>", xml_escape(feed_url)).unwrap();
+        writeln!(ret, "\t<id>{}</id>", xml_escape(feed_url)).unwrap();
+        writeln!(ret, "\t<updated>{}</updated>", rfc3339(&updated)).unwrap();
+        if let Some(a) = &self.author {
+            writeln!(ret, "\t<author>\n\t\t<name>{}</name>\n\t</author>", xml_escape(a)).unwrap();
+        }
+        for i in items {
+            ret.push_str("\t<entry>\n");
+            writeln!(ret, "\t\t<title>{}</title>", xml_escape(&i.title)).unwrap();
+            writeln!(ret, "\t\t<link href=\"{}\"/>", xml_escape(&i.url)).unwrap();
+            writeln!(ret, "\t\t<id>{}</id>", xml_escape(&i.url)).unwrap();
+            writeln!(ret, "\t\t<updated>{}</updated>", rfc3339(&i.date.unwrap_or(updated))).unwrap();
+            if let Some(s) = &i.summary {
+                writeln!(ret, "\t\t<summary>{}</summary>", xml_escape(s)).unwrap();
+            }
+            ret.push_str("\t</entry>\n");
+        }
+        ret.push_str("</feed>\n");
+        ret
+    }
+    fn json(&self, base_url: &str, feed_url: &str, items: &[Item]) -> Result<String> {
+        let items: Vec<Value> = items.iter().map(|i| {
+            // Items require content, pages are only described by their summary or title
+            let content = i.summary.as_ref().unwrap_or(&i.title);
+            let mut item = json!({ "id": i.url, "url": i.url, "title": i.title, "content_text": content });
+            if let Some(d) = i.date {
+                item["date_published"] = json!(rfc3339(&d));
+            }
+            if let Some(s) = &i.summary {
+                item["summary"] = json!(s);
+            }
+            item
+        }).collect();
+        let mut feed = json!({
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": self.title,
+            "home_page_url": base_url,
+            "feed_url": feed_url,
+            "items": items,
+        });
+        if let Some(d) = &self.description {
+            feed["description"] = json!(d);
+        }
+        if let Some(a) = &self.author {
+            feed["authors"] = json!([{ "name": a }]);
+        }
+        Ok(serde_json::to_string_pretty(&feed)? + "\n")
+    }
+}
+
+
+// Newest date of the items in a feed
+fn updated(items: &[Item]) -> Option<DateTime<FixedOffset>> {
+    items.iter().filter_map(|i| i.date).max()
+}
+
+fn rfc3339(date: &DateTime<FixedOffset>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
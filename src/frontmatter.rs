@@ -0,0 +1,58 @@
+// Terafirma static site generator
+//   Penn Bauman <me@pennbauman.com>
+use anyhow::{bail, Context as _, Result};
+use tera::Context;
+use crate::toml_to_tera;
+
+
+// Split a TOML (+++) or YAML (---) front matter block from the start of a page
+pub fn split(text: &str) -> Result<(Context, &str)> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let delim = match first_line(text) {
+        "+++" => "+++",
+        "---" => "---",
+        _ => return Ok((Context::new(), text)),
+    };
+    // Find closing delimiter
+    let start = line_end(text, 0);
+    let mut pos = start;
+    while pos < text.len() {
+        let next = line_end(text, pos);
+        if first_line(&text[pos..]) == delim {
+            let matter = &text[start..pos];
+            let context = match delim {
+                "+++" => {
+                    let value: toml::Value = toml::from_str(matter).context("Invalid TOML front matter")?;
+                    Context::from_value(toml_to_tera(&value))
+                },
+                _ => {
+                    let value: serde_yaml::Value = serde_yaml::from_str(matter).context("Invalid YAML front matter")?;
+                    match value {
+                        serde_yaml::Value::Null => Ok(Context::new()),
+                        v => Context::from_serialize(v),
+                    }
+                },
+            };
+            return match context {
+                Ok(c) => Ok((c, &text[next..])),
+                Err(_) => bail!("Front matter must be a table of values"),
+            };
+        }
+        pos = next;
+    }
+    bail!("Front matter is missing closing '{}'", delim)
+}
+
+// Get the first line of some text without its line ending
+fn first_line(text: &str) -> &str {
+    let line = text.split('\n').next().unwrap_or("");
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+// Find the start of the line following the line starting at 'pos'
+fn line_end(text: &str, pos: usize) -> usize {
+    match text[pos..].find('\n') {
+        Some(i) => pos + i + 1,
+        None => text.len(),
+    }
+}
@@ -0,0 +1,70 @@
+// Terafirma static site generator
+//   Penn Bauman <me@pennbauman.com>
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context as _, Result};
+use tera::{Tera, Context, Value};
+use crate::config::GenerateConfig;
+use crate::page::PageBuilder;
+use crate::{data, get_page_path, toml_to_tera};
+
+
+#[derive(Debug)]
+enum Source {
+    Inline(Vec<Value>),
+    File(PathBuf),
+}
+
+// Creates one page for each item in a list of data
+#[derive(Debug)]
+pub struct Generator {
+    path: String,
+    template: String,
+    source: Source,
+    context: Context,
+}
+impl Generator {
+    pub fn from_config(config: GenerateConfig) -> Result<Self> {
+        let source = match (config.data, config.source) {
+            (Some(d), None) => Source::Inline(d.iter().map(toml_to_tera).collect()),
+            (None, Some(s)) if !data::is_contained_path(&s) =>
+                bail!("Source '{}' for generated pages '{}' must be within the data directory", s.display(), config.path),
+            (None, Some(s)) => Source::File(s),
+            (Some(_), Some(_)) => bail!("Generated pages '{}' cannot have both data and a source", config.path),
+            (None, None) => bail!("Generated pages '{}' require data or a source", config.path),
+        };
+        Ok(Self {
+            path: config.path,
+            template: config.template,
+            source,
+            context: Context::from_value(toml_to_tera(&toml::Value::Table(config.context)))?,
+        })
+    }
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+    pub fn pages(&self, data_dir: &Path) -> Result<Vec<PageBuilder>> {
+        let items = match &self.source {
+            Source::Inline(items) => items.clone(),
+            Source::File(f) => match data::load_file(&data_dir.join(f))? {
+                Value::Array(a) => a,
+                _ => bail!("Data file '{}' for generated pages '{}' must contain a list", f.display(), self.path),
+            },
+        };
+        let mut ret = vec![];
+        let mut paths: HashMap<String, usize> = HashMap::new();
+        for (i, item) in items.into_iter().enumerate() {
+            let mut context = self.context.clone();
+            context.insert("item", &item);
+            let rendered = Tera::one_off(&self.path, &context, false)
+                .with_context(|| format!("Failed to render path '{}' for generated page {}", self.path, i + 1))?;
+            let path = get_page_path(&rendered)
+                .with_context(|| format!("Invalid path '{}' for generated page {}", rendered, i + 1))?;
+            if let Some(prev) = paths.insert(path.clone(), i) {
+                bail!("Generated pages {} and {} of '{}' both have the path '{}'", prev + 1, i + 1, self.path, rendered);
+            }
+            ret.push(PageBuilder::template_body(&path, context, &self.template));
+        }
+        Ok(ret)
+    }
+}
@@ -1,25 +1,81 @@
 // Terafirma static site generator
 //   Penn Bauman <me@pennbauman.com>
+use std::borrow::Cow;
 use std::fs;
+use std::collections::HashMap;
 use std::io::Read;
-use std::path::{Path, PathBuf};
-use anyhow::{bail, Result};
-use toml::{value::Table, map::Map};
-use tera::{Tera, Context};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::{bail, Context as _, Result};
+use tera::{Tera, Context, Filter, Function, Test};
+use rayon::prelude::*;
 
 mod page;
+mod frontmatter;
+mod config;
+mod site;
+mod taxonomy;
+mod data;
+mod generate;
+mod cache;
+mod error;
+mod sitemap;
+mod feed;
+mod extensions;
+pub use error::{BuildErrors, PageError};
+// Filters, functions and testers are written against the same version of Tera
+pub use tera;
 use page::PageBuilder;
+use config::{Config, PaginateConfig};
+use taxonomy::Taxonomy;
+use generate::Generator;
+use sitemap::Sitemap;
+use feed::Feed;
+use extensions::Extensions;
+
+// Records the outputs of the last build, for incremental builds
+static CACHE_FILE: &str = ".terafirma-cache.json";
+
+// Files written by a build, paths are relative to the site's root and start with '/'
+#[derive(Debug, Default)]
+pub struct BuildSummary {
+    pub pages: Vec<String>,
+    pub static_files: Vec<String>,
+    pub redirects: Vec<String>,
+    pub removed: Vec<String>,
+    // Outputs reused from the previous build
+    pub unchanged: usize,
+    pub elapsed: Duration,
+}
 
 #[derive(Debug)]
 pub struct SiteBuilder {
+    root_dir: PathBuf,
+    config_file: PathBuf,
     output_dir: PathBuf,
-    template_dir: String,
+    template_dir: PathBuf,
     static_dir: PathBuf,
     page_dir: PathBuf,
+    data_dir: PathBuf,
+    cache_file: PathBuf,
+    default_template: Option<String>,
+    base_url: Option<String>,
+    override_static: bool,
+    incremental: bool,
+    keep_going: bool,
+    jobs: usize,
 
     context: Context,
 
     pages: Vec<PageBuilder>,
+    paginate: HashMap<String, PaginateConfig>,
+    taxonomies: Vec<Taxonomy>,
+    generators: Vec<Generator>,
+    sitemap: Option<Sitemap>,
+    feeds: Vec<Feed>,
+    extensions: Extensions,
+    warnings: Vec<String>,
 }
 impl SiteBuilder {
     pub fn from_file<P: Into<PathBuf>>(file_path: P) -> Result<Self> {
@@ -29,169 +85,616 @@ impl SiteBuilder {
         let mut file = fs::File::open(&path)?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
-        let data: toml::Value = toml::from_str(&contents)?;
-        //println!("{:?}", data);
-        // Extract settings from TOML
-        let settings: Table = match data.get("settings") {
-            Some(v) => match v.as_table() {
-                Some(t) => t.clone(),
-                None => Map::new(),
-            },
-            None => Map::new(),
-        };
-        //println!("toml parsed");
+        let config: Config = toml::from_str(&contents)
+            .with_context(|| format!("Invalid configuration file '{}'", path.display()))?;
+        //println!("{:?}", config);
+        let settings = config.settings;
 
         // Determine directory paths
-        let output_path = create_path(root_dir, settings.get("output_dir"), "output");
-        let template_path = create_path(root_dir, settings.get("template_dir"), "templates");
-        let static_path = create_path(root_dir, settings.get("static_dir"), "static");
-        let page_path = create_path(root_dir, settings.get("page_dir"), "pages");
+        let output_path = create_path(root_dir, settings.output_dir, "output");
+        let template_path = create_path(root_dir, settings.template_dir, "templates");
+        let static_path = create_path(root_dir, settings.static_dir, "static");
+        let page_path = create_path(root_dir, settings.page_dir, "pages");
+        let data_path = create_path(root_dir, settings.data_dir, "data");
         //println!("paths created");
 
         // Create return value
         let mut ret = Self {
+            root_dir: root_dir.to_path_buf(),
+            config_file: path.clone(),
             output_dir: output_path,
-            template_dir: template_path.to_str().unwrap().to_owned() + "/**/*",
+            template_dir: template_path,
             static_dir: static_path,
             page_dir: page_path,
-            context: match data.get("context") {
-                Some(v) => Context::from_serialize(v)?,
-                None => Context::new(),
-            },
+            data_dir: data_path,
+            cache_file: root_dir.join(CACHE_FILE),
+            default_template: settings.default_template,
+            base_url: settings.base_url,
+            override_static: settings.override_static,
+            incremental: true,
+            keep_going: false,
+            jobs: 0,
+            context: Context::from_value(toml_to_tera(&toml::Value::Table(config.context)))?,
             pages: vec![],
+            paginate: HashMap::new(),
+            taxonomies: vec![],
+            generators: vec![],
+            sitemap: None,
+            feeds: vec![],
+            extensions: Extensions::default(),
+            warnings: vec![],
         };
         //println!("ret created");
 
+        // Errors in entries name the entry and the configuration file
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+
         // Parse redirects
-        let arr = match data.get("redirect") {
-            Some(v) => match v.as_array() {
-                Some(a) => a.clone(),
-                None => vec![],
-            },
-            None => vec![],
-        };
-        for r in arr {
+        for (i, r) in config.redirect.into_iter().enumerate() {
             //println!("redirect: {:?}", r);
-            let path = get_page_path(r.get("path"))?;
-            let url = match r.get("url") {
-                Some(v) => match v.as_str() {
-                    Some(s) => s,
-                    None => bail!("Redirect URL must be a string"),
-                },
-                None => bail!("All redirects require a URL"),
-            };
-            ret.pages.push(PageBuilder::redirect(&path, url));
+            let path = get_page_path(&r.path)
+                .with_context(|| format!("Invalid path '{}' for [[redirect]] {} in '{}'", r.path, i + 1, file_name))?;
+            ret.pages.push(PageBuilder::redirect(&path, &r.url));
         }
 
         // Parse pages
-        let arr = match data.get("page") {
-            Some(v) => match v.as_array() {
-                Some(a) => a.clone(),
-                None => vec![],
-            },
-            None => vec![],
-        };
-        for p in arr {
+        for (i, p) in config.page.into_iter().enumerate() {
             //println!("page: {:?}", p);
-            let path = get_page_path(p.get("path"))?;
-            let context = match p.get("context") {
-                Some(v) => Context::from_serialize(v)?,
-                None => Context::new(),
+            let path = get_page_path(&p.path)
+                .with_context(|| format!("Invalid path '{}' for [[page]] {} in '{}'", p.path, i + 1, file_name))?;
+            // Keys other than page options are added to the page's context
+            let mut context = Context::new();
+            for (key, value) in &p.extra {
+                if p.context.contains_key(key) {
+                    ret.warnings.push(format!("'{}' for page '/{}' is ignored, it is overridden by the page's context", key, path));
+                }
+                context.insert(key, &toml_to_tera(value));
+            }
+            context.extend(Context::from_value(toml_to_tera(&toml::Value::Table(p.context)))?);
+            if let Some(pg) = p.paginate {
+                if pg.per_page == 0 {
+                    bail!("Pagination for page '/{}' must have at least 1 item per page", path);
+                }
+                ret.paginate.insert(path.clone(), pg);
+            }
+            match p.body {
+                Some(s) => ret.pages.push(PageBuilder::text_body(&path, context, &s, p.format, p.template)),
+                None => ret.pages.push(PageBuilder::no_body(&path, context, p.format, p.template)),
             };
-            match p.get("body") {
-                Some(v) => match v.as_str() {
-                    Some(s) => ret.pages.push(PageBuilder::text_body(&path, context, s)),
-                    None => bail!("Page bodies must be strings"),
-                },
-                None => ret.pages.push(PageBuilder::no_body(&path, context)),
+        }
+
+        // Parse taxonomies
+        for t in config.taxonomy {
+            if ret.taxonomies.iter().any(|x| x.name() == t.name) {
+                bail!("Duplicate taxonomy '{}'", t.name);
+            }
+            let path = match &t.path {
+                Some(p) => get_dir_path(p)
+                    .with_context(|| format!("Invalid path '{}' for [[taxonomy]] '{}' in '{}'", p, t.name, file_name))?,
+                None => get_dir_path(&format!("/{}", t.name))
+                    .with_context(|| format!("Invalid name for [[taxonomy]] '{}' in '{}'", t.name, file_name))?,
             };
+            ret.taxonomies.push(Taxonomy::from_config(t, path));
         }
 
-        return Ok(ret);
+        // Parse generated pages
+        for (i, g) in config.generate.into_iter().enumerate() {
+            ret.generators.push(Generator::from_config(g).with_context(|| format!("Invalid [[generate]] {} in '{}'", i + 1, file_name))?);
+        }
+
+        // Parse sitemap
+        if let Some(s) = config.sitemap {
+            let path = get_page_path(s.path.as_deref().unwrap_or("/sitemap.xml"))
+                .with_context(|| format!("Invalid path for [sitemap] in '{}'", file_name))?;
+            ret.sitemap = Some(Sitemap::new(path));
+        }
+
+        // Parse feeds
+        for (i, f) in config.feed.into_iter().enumerate() {
+            ret.feeds.push(Feed::from_config(f).with_context(|| format!("Invalid [[feed]] {} in '{}'", i + 1, file_name))?);
+        }
+
+        Ok(ret)
+    }
+    pub fn build(&mut self) -> Result<BuildSummary> {
+        let start = Instant::now();
+        self.check_output_dir()?;
+        self.check_output_nesting()?;
+        self.check_base_url()?;
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(self.jobs).build()?;
+        // A swap which was interrupted leaves the previous output aside, restore it before anything else
+        let old = self.sibling_dir("old");
+        if !self.output_dir.exists() && old.is_dir() {
+            fs::rename(&old, &self.output_dir)?;
+        }
+        remove_dir_if_exists(&old)?;
+        // Build in a staging directory, so a failed build leaves the previous output untouched
+        let staging = self.sibling_dir("staging");
+        remove_dir_if_exists(&staging)?;
+        let (manifest, mut summary) = match pool.install(|| self.build_site(&staging)) {
+            Ok(r) => r,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&staging);
+                return Err(e);
+            },
+        };
+        // Swap the new output into place, putting the previous output back if that fails
+        let moved = self.output_dir.exists();
+        if moved {
+            if let Err(e) = fs::rename(&self.output_dir, &old) {
+                let _ = fs::remove_dir_all(&staging);
+                return Err(e).context("Failed to move the previous output aside");
+            }
+        }
+        if let Err(e) = fs::rename(&staging, &self.output_dir) {
+            if moved {
+                let _ = fs::rename(&old, &self.output_dir);
+            }
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e).context("Failed to move the new output into place");
+        }
+        remove_dir_if_exists(&old)?;
+        // Outputs rendered with extensions cannot be reused by later builds either
+        match self.extensions.is_empty() {
+            true => manifest.save(&self.cache_file)?,
+            false => remove_if_exists(&self.cache_file)?,
+        }
+        summary.elapsed = start.elapsed();
+        Ok(summary)
     }
-    pub fn build(&mut self) -> Result<()> {
-        let mut tera = Tera::new(&self.template_dir)?;
+    fn build_site(&self, output: &Path) -> Result<(cache::Manifest, BuildSummary)> {
+        let mut tera = Tera::new(&self.template_glob())?;
 
         //println!("build start ...");
-        self.clean()?;
+        // Outputs from the previous build are reused when their dependencies are unchanged
+        // Extensions are code which cannot be fingerprinted, so nothing is reused while there are any
+        let previous = match self.incremental && self.extensions.is_empty() {
+            true => cache::Manifest::load(&self.cache_file, &self.output_dir),
+            false => None,
+        };
+        match previous {
+            Some(_) => link_dir(&self.output_dir, output)?,
+            None => fs::create_dir_all(output)?,
+        }
+        let mut manifest = cache::Manifest::new(&self.output_dir);
+        let mut summary = BuildSummary::default();
 
-        fs::create_dir_all(&self.output_dir)?;
-        for f in recursive_ls(&self.static_dir)? {
-            //println!("static: {}", f);
-            match Path::new(&f).parent() {
-                Some(p) => fs::create_dir_all(&self.output_dir.join(p))?,
-                None => (),
+        //println!("{:?}", recursive_ls(&self.page_dir));
+        // Page files are added to a copy, so the builder can build again
+        let mut pages = self.pages.clone();
+        // Page files only give bodies to [[page]] entries without one, other collisions are reported below
+        let mut by_path: HashMap<String, usize> = pages.iter().enumerate()
+            .filter(|(_, p)| matches!(p, PageBuilder::WithoutBody{ .. }))
+            .map(|(i, p)| (p.path().to_string(), i))
+            .collect();
+        let mut files = recursive_ls(&self.page_dir)?;
+        files.sort();
+        // Page files which cannot be read are reported along with pages which fail to render
+        let mut errors = vec![];
+        for f in files {
+            //println!("page: {}", f);
+            let path = page::file_page_path(&f);
+            let result = match by_path.remove(&path) {
+                Some(i) => pages[i].add_file_body(&f, &self.page_dir),
+                None => PageBuilder::file_body(&path, &f, &self.page_dir).map(|p| pages.push(p)),
             };
-            fs::copy(&self.static_dir.join(&f), &self.output_dir.join(&f))?;
+            if let Err(error) = result {
+                errors.push(PageError { path: format!("/{}", path), file: self.relative(&self.page_dir.join(&f)), error });
+            }
+        }
+        for g in &self.generators {
+            pages.extend(g.pages(&self.data_dir)?);
         }
-        //println!("static copied");
 
-        //println!("{:?}", recursive_ls(&self.page_dir));
-        for f in recursive_ls(&self.page_dir)? {
-            let mut dup = false;
-            for p in &mut self.pages {
-                //println!("page: {}", f);
-                if p.path() == f {
-                    dup = true;
-                    p.add_file_body(&f)?;
-                    break;
+
+        site::register_filters(&mut tera);
+        data::register_functions(&mut tera, &self.root_dir, &self.static_dir);
+        let mut site = site::collect(&pages);
+        let mut generated = vec![];
+        let mut taxonomies = tera::Map::new();
+        for t in &self.taxonomies {
+            let value = t.collect(&site)?;
+            generated.extend(t.pages(&value));
+            taxonomies.insert(t.name().to_string(), value);
+        }
+        site["taxonomies"] = tera::Value::Object(taxonomies);
+        site["base_url"] = tera::to_value(&self.base_url)?;
+        site::register_functions(&mut tera, self.base_url.as_deref(), &site);
+        self.extensions.apply(&mut tera)?;
+        let mut context = self.context.clone();
+        context.insert("site", &site);
+        context.insert("data", &data::load_dir(&self.data_dir)?);
+        // Every page depends on the base URL, since any template can call url_for()
+        let global = format!("{:?} {:?}", self.context, self.base_url);
+        let deps = cache::Dependencies::new(&self.template_dir, &self.data_dir, &global, &site.to_string())?;
+        let default_template = self.default_template.as_deref();
+        let mut outputs = vec![];
+        for p in pages.iter().chain(&generated) {
+            match self.paginate.get(p.path()) {
+                Some(pg) => {
+                    for (path, paginator) in site::paginate(&site, p.path(), pg)? {
+                        outputs.push(Cow::Owned(p.paginated(&path, &paginator)));
+                    }
+                },
+                None => outputs.push(Cow::Borrowed(p)),
+            }
+        }
+        // Render and report errors in path order, which is the same on every file system
+        outputs.sort_by(|a, b| a.path().cmp(b.path()));
+        // Pages with page files that could not be read are already reported
+        outputs.retain(|p| !errors.iter().any(|e: &PageError| e.path[1..] == *p.path()));
+        // Files listing the site's pages
+        let config_name = self.relative(&self.config_file);
+        let mut listings = vec![];
+        if let Some(sitemap) = &self.sitemap {
+            let pages: Vec<&PageBuilder> = outputs.iter().map(|p| p.as_ref()).collect();
+            for (path, contents) in sitemap.files(self.base_url.as_deref().unwrap_or_default(), &pages)? {
+                listings.push((path, contents, format!("[sitemap] in '{}'", config_name.display())));
+            }
+        }
+        for feed in &self.feeds {
+            for (path, contents) in feed.files(self.base_url.as_deref().unwrap_or_default(), &site)? {
+                listings.push((path, contents, format!("[[feed]] '{}' in '{}'", feed.title(), config_name.display())));
+            }
+        }
+
+        // Plan every output before anything is written, so collisions are found first
+        let mut plan: HashMap<&str, String> = HashMap::new();
+        for page in &outputs {
+            let source = self.describe_source(page);
+            if let Some(other) = plan.insert(page.path(), source) {
+                bail!("Output '/{}' is produced by both {} and {}", page.path(), other, self.describe_source(page));
+            }
+        }
+        for (path, _, source) in &listings {
+            if let Some(other) = plan.insert(path, source.clone()) {
+                bail!("Output '/{}' is produced by both {} and {}", path, other, source);
+            }
+        }
+        let mut copies = vec![];
+        for f in recursive_ls(&self.static_dir)? {
+            if let Some(other) = plan.get(f.as_str()) {
+                if self.override_static {
+                    continue;
                 }
+                bail!("Output '/{}' is produced by both static file '{}' and {}", f, self.relative(&self.static_dir.join(&f)).display(),
+                    other);
             }
-            if !dup {
-                self.pages.push(PageBuilder::file_body(&f, &f));
+            let changed = manifest.add_static(previous.as_ref(), &f, &self.static_dir.join(&f))?;
+            if changed || !output.join(&f).is_file() {
+                copies.push(f);
+            } else {
+                summary.unchanged += 1;
             }
         }
+        // Copy in parallel, reporting the first error in file order
+        copies.sort();
+        copies.par_iter().map(|f| -> Result<()> {
+            //println!("static: {}", f);
+            if let Some(p) = Path::new(f).parent() {
+                fs::create_dir_all(output.join(p))?;
+            }
+            // Replace rather than overwrite, the file may be linked to the previous output
+            remove_if_exists(&output.join(f))?;
+            fs::copy(self.static_dir.join(f), output.join(f))
+                .with_context(|| format!("Failed to copy static file '{}'", f))?;
+            Ok(())
+        }).collect::<Vec<_>>().into_iter().collect::<Result<()>>()?;
+        //println!("static copied");
 
+        outputs.retain(|page| {
+            let (hash, dependencies) = deps.page(page, default_template);
+            let changed = manifest.add_output(previous.as_ref(), page.path(), hash, dependencies);
+            changed || !output.join(page.path()).is_file()
+        });
+        summary.unchanged += manifest.output_count() - outputs.len();
+        // Render in parallel, each thread needs its own Tera to render strings and context to add page values to
+        let results: Vec<Result<()>> = outputs.par_iter()
+            .map_init(|| (tera.clone(), context.clone()), |(tera, context), page| page.build(tera, context, default_template, output))
+            .collect();
+        // Report the first error in path order, or every error when keeping going
+        for (page, result) in outputs.iter().zip(results) {
+            if let Err(error) = result {
+                errors.push(PageError { path: format!("/{}", page.path()), file: self.page_source(page), error });
+            }
+        }
+        errors.sort_by(|a, b| a.path.cmp(&b.path));
+        if self.keep_going && !errors.is_empty() {
+            return Err(BuildErrors { pages: errors }.into());
+        }
+        if let Some(e) = errors.into_iter().next() {
+            return Err(e.into());
+        }
+        for (path, contents, _) in listings {
+            let changed = manifest.add_output(previous.as_ref(), &path, cache::hash(&contents), vec!["site".to_string()]);
+            if changed || !output.join(&path).is_file() {
+                write_file(&output.join(&path), &contents)?;
+                summary.pages.push(format!("/{}", path));
+            } else {
+                summary.unchanged += 1;
+            }
+        }
 
-        for p in &self.pages {
-            p.build(&mut tera, &self.context, &self.output_dir, &self.page_dir)?;
+        // Remove outputs which are no longer part of the site
+        if let Some(prev) = &previous {
+            for p in manifest.removed(prev) {
+                remove_if_exists(&output.join(p))?;
+                summary.removed.push(format!("/{}", p));
+            }
         }
 
+        summary.static_files = copies.iter().map(|f| format!("/{}", f)).collect();
+        for p in outputs {
+            match p.as_ref() {
+                PageBuilder::Redirect{ path, .. } => summary.redirects.push(format!("/{}", path)),
+                page => summary.pages.push(format!("/{}", page.path())),
+            }
+        }
+        summary.pages.sort();
+        summary.redirects.sort();
+        Ok((manifest, summary))
+    }
+    pub fn output_dir(&self) -> &Path {
+        &self.output_dir
+    }
+    // Problems in the configuration which do not stop the site being built
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+    // Directories containing files used to build the site
+    pub fn source_dirs(&self) -> Vec<&Path> {
+        vec![&self.template_dir, &self.static_dir, &self.page_dir, &self.data_dir]
+    }
+    pub fn check(&self) -> Result<()> {
+        self.check_output_dir()?;
+        self.check_output_nesting()?;
+        self.check_base_url()?;
+        let mut tera = Tera::new(&self.template_glob())?;
+        self.extensions.apply(&mut tera)?;
+        data::load_dir(&self.data_dir)?;
+        let templates = self.pages.iter().filter_map(|p| p.template())
+            .chain(self.taxonomies.iter().flat_map(|t| t.templates()))
+            .chain(self.generators.iter().map(|g| g.template()));
+        for t in self.default_template.iter().map(|t| t.as_str()).chain(templates) {
+            if !t.is_empty() && !tera.get_template_names().any(|n| n == t) {
+                bail!("Layout template '{}' not found", t);
+            }
+        }
         Ok(())
     }
-    pub fn clean(&self) -> Result<()> {
-        match fs::remove_dir_all(&self.output_dir) {
-            Ok(_) => Ok(()),
-            Err(e) => match e.raw_os_error() {
-                Some(2) => Ok(()),
-                _ => Err(e.into()),
-            },
+    // Sitemaps and feeds link to pages with absolute URLs, so they require the base URL
+    fn check_base_url(&self) -> Result<()> {
+        if self.base_url.is_some() {
+            return Ok(());
+        }
+        let file_name = self.config_file.file_name().unwrap_or_default().to_string_lossy();
+        if self.sitemap.is_some() {
+            bail!("[sitemap] in '{}' requires base_url in [settings]", file_name);
         }
+        if let Some(f) = self.feeds.first() {
+            bail!("[[feed]] '{}' in '{}' requires base_url in [settings]", f.title(), file_name);
+        }
+        Ok(())
+    }
+    // File a page comes from, relative to the project if possible
+    fn page_source(&self, page: &PageBuilder) -> PathBuf {
+        match page {
+            PageBuilder::FileBody{ file, .. } => self.relative(&self.page_dir.join(file)),
+            PageBuilder::TemplateBody{ template, .. } => self.relative(&self.template_dir.join(template)),
+            _ => self.relative(&self.config_file),
+        }
+    }
+    fn describe_source(&self, page: &PageBuilder) -> String {
+        let file = self.page_source(page);
+        match page {
+            PageBuilder::Redirect{ .. } => format!("[[redirect]] in '{}'", file.display()),
+            PageBuilder::FileBody{ .. } => format!("page file '{}'", file.display()),
+            PageBuilder::TemplateBody{ .. } => format!("generated page using '{}'", file.display()),
+            _ => format!("[[page]] in '{}'", file.display()),
+        }
+    }
+    fn relative(&self, file: &Path) -> PathBuf {
+        match file.strip_prefix(&self.root_dir) {
+            Ok(p) => p.to_path_buf(),
+            Err(_) => file.to_path_buf(),
+        }
+    }
+    // The output directory is deleted and replaced, so it must not contain the project or its sources
+    fn check_output_dir(&self) -> Result<()> {
+        let output = normalize_path(&self.output_dir);
+        let dirs = [("project", &self.root_dir), ("template", &self.template_dir), ("static", &self.static_dir),
+            ("page", &self.page_dir), ("data", &self.data_dir)];
+        for (name, dir) in dirs {
+            if normalize_path(dir).starts_with(&output) {
+                bail!("Output directory '{}' contains the {} directory '{}', refusing to delete it",
+                    self.output_dir.display(), name, dir.display());
+            }
+        }
+        Ok(())
+    }
+    // An output directory inside a source directory would be read back in by the next build,
+    // along with the staging directory next to it
+    fn check_output_nesting(&self) -> Result<()> {
+        let output = normalize_path(&self.output_dir);
+        let dirs = [("template", &self.template_dir), ("static", &self.static_dir), ("page", &self.page_dir),
+            ("data", &self.data_dir)];
+        for (name, dir) in dirs {
+            if output.starts_with(normalize_path(dir)) {
+                bail!("Output directory '{}' is inside the {} directory '{}', so builds would read their own output",
+                    self.output_dir.display(), name, dir.display());
+            }
+        }
+        Ok(())
+    }
+    // Path next to the output directory used while building, such as '.output.staging'
+    fn sibling_dir(&self, suffix: &str) -> PathBuf {
+        let name = self.output_dir.file_name().unwrap_or_default().to_string_lossy();
+        self.output_dir.with_file_name(format!(".{}.{}", name, suffix))
+    }
+    fn template_glob(&self) -> String {
+        self.template_dir.to_str().unwrap().to_owned() + "/**/*"
+    }
+    // Incremental builds only rebuild outputs whose dependencies changed since the previous build
+    pub fn set_incremental(&mut self, incremental: bool) {
+        self.incremental = incremental;
+    }
+    // Build every page even after one fails, reporting all failures together
+    pub fn set_keep_going(&mut self, keep_going: bool) {
+        self.keep_going = keep_going;
+    }
+    // Override the base_url setting, such as to build for a staging server
+    pub fn set_base_url(&mut self, base_url: &str) {
+        self.base_url = Some(base_url.to_string());
+    }
+    // Add a Tera filter for every page, replacing any built in filter with the same name
+    pub fn register_filter<F: Filter + 'static>(&mut self, name: &str, filter: F) {
+        self.extensions.add_filter(name, Arc::new(filter));
+    }
+    // Add a Tera function for every page, replacing any built in function with the same name
+    pub fn register_function<F: Function + 'static>(&mut self, name: &str, function: F) {
+        self.extensions.add_function(name, Arc::new(function));
+    }
+    // Add a Tera tester for every page, replacing any built in tester with the same name
+    pub fn register_tester<T: Test + 'static>(&mut self, name: &str, tester: T) {
+        self.extensions.add_tester(name, Arc::new(tester));
+    }
+    // Run a function on the Tera instance pages are rendered with, after everything else is registered
+    pub fn configure_tera(&mut self, hook: impl Fn(&mut Tera) -> Result<()> + Send + Sync + 'static) {
+        self.extensions.add_hook(Box::new(hook));
+    }
+    // Number of threads used to build the site, 0 uses one per CPU core
+    pub fn set_jobs(&mut self, jobs: usize) {
+        self.jobs = jobs;
+    }
+    pub fn clean(&self) -> Result<()> {
+        self.check_output_dir()?;
+        remove_if_exists(&self.cache_file)?;
+        remove_dir_if_exists(&self.output_dir)
     }
 }
 
 
-fn get_page_path(src: Option<&toml::Value>) -> Result<String> {
-    let path = match src {
-        Some(v) => match v.as_str() {
-            Some(s) => s,
-            None => bail!("Page path must be a string"),
-        },
-        None => bail!("Missing page path"),
-    };
+// Convert TOML values for use in Tera, dates become strings
+pub(crate) fn toml_to_tera(value: &toml::Value) -> tera::Value {
+    match value {
+        toml::Value::String(s) => tera::Value::String(s.clone()),
+        toml::Value::Integer(i) => tera::Value::from(*i),
+        toml::Value::Float(f) => tera::Value::from(*f),
+        toml::Value::Boolean(b) => tera::Value::Bool(*b),
+        toml::Value::Datetime(d) => tera::Value::String(d.to_string()),
+        toml::Value::Array(a) => tera::Value::Array(a.iter().map(toml_to_tera).collect()),
+        toml::Value::Table(t) => tera::Value::Object(t.iter().map(|(k, v)| (k.clone(), toml_to_tera(v))).collect()),
+    }
+}
+
+// Normalize a page path, removing the leading '/' and rejecting paths which could escape the site
+fn get_page_path(path: &str) -> Result<String> {
     if path.is_empty() {
         bail!("Page path cannot be an empty string");
     }
-    if path.starts_with("/") {
-        return Ok(path.strip_prefix("/").unwrap().to_string());
-    } else {
-        bail!("Page path must start with '/'");
+    let rest = match path.strip_prefix('/') {
+        Some(p) => p,
+        None => bail!("Page path must start with '/'"),
+    };
+    if path.contains('\0') {
+        bail!("Page path cannot contain NUL bytes");
+    }
+    if path.contains('\\') {
+        bail!("Page path cannot contain '\\'");
+    }
+    let mut parts = vec![];
+    for part in rest.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => bail!("Page path cannot contain '..'"),
+            p => parts.push(p),
+        }
+    }
+    if parts.is_empty() {
+        bail!("Page path must include a file name");
+    }
+    // Directory paths would be written as a file without an extension
+    if matches!(rest.rsplit('/').next(), Some("" | ".")) {
+        bail!("Page path must end with a file name, such as '/{}/index.html'", parts.join("/"));
+    }
+    Ok(parts.join("/"))
+}
+
+// Directories in the site are page paths which may end in '/', or '/' for the site's root
+fn get_dir_path(path: &str) -> Result<String> {
+    match path.trim_end_matches('/') {
+        "" if path.starts_with('/') => Ok(String::new()),
+        p => get_page_path(p),
+    }
+}
+
+// Resolve '.' and '..' without requiring the path to exist, following links when it does
+fn normalize_path(path: &Path) -> PathBuf {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let mut ret = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                ret.pop();
+            },
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+fn create_path(root: &Path, dir: Option<PathBuf>, default: &str) -> PathBuf {
+    match dir {
+        Some(p) if p.is_absolute() => p,
+        Some(p) => root.join(p),
+        None => root.join(default),
     }
 }
 
-fn create_path(root: &Path, val: Option<&toml::Value>, default: &str) -> PathBuf {
-    let dir = match val {
-        Some(v) => match v.as_str() {
-            Some(s) => s,
-            None => return root.join(default),
+// Write a file, replacing rather than overwriting it as it may be linked to the previous output
+pub(crate) fn write_file(file: &Path, contents: &str) -> Result<()> {
+    if let Some(p) = file.parent() {
+        fs::create_dir_all(p)?;
+    }
+    remove_if_exists(file)?;
+    fs::write(file, contents)?;
+    Ok(())
+}
+
+pub(crate) fn remove_if_exists(file: &Path) -> Result<()> {
+    match fs::remove_file(file) {
+        Ok(_) => Ok(()),
+        Err(e) => match e.raw_os_error() {
+            Some(2) => Ok(()),
+            _ => Err(e.into()),
         },
-        None => return root.join(default),
-    };
-    let mut path = PathBuf::from(dir);
-    if !path.is_absolute() {
-        path = root.join(path);
     }
-    return path.to_path_buf();
+}
+
+fn remove_dir_if_exists(dir: &Path) -> Result<()> {
+    match fs::remove_dir_all(dir) {
+        Ok(_) => Ok(()),
+        Err(e) => match e.raw_os_error() {
+            Some(2) => Ok(()),
+            _ => Err(e.into()),
+        },
+    }
+}
+
+// Fill a directory with links to the files in another, copying files which cannot be linked
+fn link_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for f in recursive_ls(from)? {
+        let target = to.join(&f);
+        if let Some(p) = target.parent() {
+            fs::create_dir_all(p)?;
+        }
+        if fs::hard_link(from.join(&f), &target).is_err() {
+            fs::copy(from.join(&f), &target)?;
+        }
+    }
+    Ok(())
 }
 
 fn recursive_ls(dir: &Path) -> Result<Vec<String>> {
@@ -203,8 +706,7 @@ fn recursive_ls(dir: &Path) -> Result<Vec<String>> {
         return Ok(ret);
     }
     let mut subdirs = vec![PathBuf::from("")];
-    while subdirs.len() > 0 {
-        let prefix = subdirs.pop().unwrap();
+    while let Some(prefix) = subdirs.pop() {
         for entry in fs::read_dir(dir.join(&prefix))? {
             //println!("{:?}", entry);
             match entry {
@@ -213,15 +715,14 @@ fn recursive_ls(dir: &Path) -> Result<Vec<String>> {
                     if kind.is_dir() {
                         subdirs.push(prefix.join(e.file_name()));
                     } else if kind.is_file() {
-                        match prefix.join(e.file_name()).to_str() {
-                            Some(s) => ret.push(s.to_string()),
-                            None => (),
-                        };
+                        if let Some(s) = prefix.join(e.file_name()).to_str() {
+                            ret.push(s.to_string());
+                        }
                     }
                 },
                 Err(e) => return Err(e.into()),
             };
         }
     }
-    return Ok(ret);
+    Ok(ret)
 }
@@ -2,11 +2,22 @@
 //   Penn Bauman <me@pennbauman.com>
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::path::PathBuf;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::thread;
 use clap::{Parser, Subcommand};
 use anyhow::Result;
 use terafirma::SiteBuilder;
 
+mod serve;
+mod watch;
+mod report;
+use watch::Watcher;
+use serve::LiveReload;
+use report::{MessageFormat, Reporter};
+
 static NEW_TOML: &str = "[page]
 path = \"/index.html\"
 body = \"<p>Hello world!</p>\"
@@ -22,40 +33,170 @@ struct Cli {
     /// Select custom config file, the default is 'Terafirma.toml'
     #[arg(short, long, value_name = "FILE")]
     config: Option<PathBuf>,
+
+    /// Number of threads used to build the site, the default is one per CPU core
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// URL the site is hosted at, overriding base_url in the config file
+    #[arg(long, value_name = "URL")]
+    base_url: Option<String>,
+
+    /// Only print errors
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Also print every file written or removed by a build
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Print messages for people or as JSON objects, one per line
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = MessageFormat::Human)]
+    message_format: MessageFormat,
 }
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Build static site, default command if unspecified
-    Build {},
+    Build {
+        /// Rebuild the site whenever its source files change
+        #[arg(short, long)]
+        watch: bool,
+        /// Rebuild every page, ignoring outputs from the previous build
+        #[arg(long)]
+        full: bool,
+        /// Build every page even after one fails, then report every failure
+        #[arg(short, long)]
+        keep_going: bool,
+    },
+    /// Check configuration file for errors without building
+    Check {},
     /// Clean up already build site
     Clean {},
     /// Create new configuration file in the current directory
     New {},
+    /// Build static site and serve it locally
+    Serve {
+        /// Port to listen on
+        #[arg(short, long, default_value_t = 8000)]
+        port: u16,
+        /// Address to listen on
+        #[arg(short, long, default_value = "127.0.0.1")]
+        bind: String,
+        /// Do not rebuild the site when its source files change
+        #[arg(long)]
+        no_watch: bool,
+    },
 }
 
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
     let cli = Cli::parse();
+    let reporter = Reporter { format: cli.message_format, quiet: cli.quiet, verbose: cli.verbose };
+    match run(cli, reporter) {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            reporter.error(&e);
+            ExitCode::FAILURE
+        },
+    }
+}
+
+fn run(cli: Cli, reporter: Reporter) -> Result<()> {
     let file_path = cli.config.unwrap_or(PathBuf::from("Terafirma.toml"));
+    let jobs = cli.jobs.unwrap_or(0);
+    let base_url = cli.base_url.as_deref();
 
     match cli.command {
-        Some(Commands::Build { }) | None => {
-            let mut builder = SiteBuilder::from_file(file_path)?;
-            println!("Build: {:?}", builder.build());
+        Some(Commands::Build { watch, full, keep_going }) => {
+            let mut builder = load_site(&file_path, jobs, base_url, reporter)?;
+            builder.set_incremental(!full);
+            builder.set_keep_going(keep_going);
+            match watch {
+                true => watch_site(&file_path, jobs, base_url, reporter, builder, |_| ())?,
+                false => reporter.build(&builder.build()?),
+            }
+        },
+        None => {
+            let mut builder = load_site(&file_path, jobs, base_url, reporter)?;
+            reporter.build(&builder.build()?);
+        },
+        Some(Commands::Check { }) => {
+            let builder = load_site(&file_path, jobs, base_url, reporter)?;
+            builder.check()?;
+            reporter.message("Check", &format!("{} is valid", file_path.display()));
         },
         Some(Commands::Clean { }) => {
             let builder = SiteBuilder::from_file(file_path)?;
-            println!("Clean: {:?}", builder.clean());
+            builder.clean()?;
+            reporter.message("Clean", &format!("removed {}", builder.output_dir().display()));
+        },
+        Some(Commands::Serve { port, bind, no_watch }) => {
+            let mut builder = load_site(&file_path, jobs, base_url, reporter)?;
+            let listener = TcpListener::bind((bind.as_str(), port))?;
+            let output_dir = builder.output_dir().to_path_buf();
+            if no_watch {
+                reporter.build(&builder.build()?);
+                reporter.message("Serving", &format!("http://{}", listener.local_addr()?));
+                serve::serve(&output_dir, listener, None)?;
+            } else {
+                reporter.message("Serving", &format!("http://{}", listener.local_addr()?));
+                let reload = Arc::new(LiveReload::default());
+                let server_reload = reload.clone();
+                thread::spawn(move || serve::serve(&output_dir, listener, Some(server_reload)));
+                watch_site(&file_path, jobs, base_url, reporter, builder, |changed| reload.notify(changed))?;
+            }
         },
         Some(Commands::New { }) => {
             let mut file = OpenOptions::new().write(true).create_new(true).open(&file_path)?;
             write!(&mut file, "{}", NEW_TOML)?;
-            println!("New: {}", file_path.display());
+            reporter.message("New", &file_path.display().to_string());
         },
     }
     Ok(())
 }
 
+fn load_site(file_path: &Path, jobs: usize, base_url: Option<&str>, reporter: Reporter) -> Result<SiteBuilder> {
+    let mut builder = SiteBuilder::from_file(file_path)?;
+    for w in builder.warnings() {
+        reporter.warning(w);
+    }
+    builder.set_jobs(jobs);
+    if let Some(u) = base_url {
+        builder.set_base_url(u);
+    }
+    Ok(builder)
+}
+
+// Build the site, then rebuild it whenever its configuration or source files change
+fn watch_site(file_path: &Path, jobs: usize, base_url: Option<&str>, reporter: Reporter, mut builder: SiteBuilder, mut on_rebuild: impl FnMut(&[PathBuf])) -> Result<()> {
+    // Files changed since the last successful build
+    let mut pending: Vec<PathBuf> = vec![];
+    loop {
+        let mut paths = vec![file_path.to_path_buf()];
+        paths.extend(builder.source_dirs().iter().map(|p| p.to_path_buf()));
+        let mut watcher = Watcher::new(paths, vec![builder.output_dir().to_path_buf()]);
+        match builder.build() {
+            Ok(summary) => {
+                if !pending.is_empty() {
+                    on_rebuild(&pending);
+                    pending.clear();
+                }
+                reporter.build(&summary);
+            },
+            Err(e) => reporter.error(&e),
+        }
+        builder = loop {
+            let changed = watcher.wait();
+            pending.extend(changed.iter().cloned());
+            reporter.message("Changed", &changed.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", "));
+            match load_site(file_path, jobs, base_url, reporter) {
+                Ok(b) => break b,
+                Err(e) => reporter.error(&e),
+            }
+        };
+    }
+}
+
 
 #[test]
 fn verify_cli() {
@@ -1,10 +1,13 @@
 // Terafirma static site generator
 //   Penn Bauman <me@pennbauman.com>
 use std::fs;
-use std::io::{Read, Write};
+use std::io::Read;
 use std::path::{Path};
-use anyhow::{anyhow, bail, Result};
-use tera::{Tera, Context};
+use anyhow::{anyhow, bail, Context as _, Result};
+use tera::{Tera, Context, Value};
+use pulldown_cmark::{Parser, Options, html};
+use serde::Deserialize;
+use crate::{frontmatter, site};
 
 
 static REDIRECT_TEMPLATE: &str = "<!DOCTYPE html><html>
@@ -13,20 +16,82 @@ static REDIRECT_TEMPLATE: &str = "<!DOCTYPE html><html>
 </html>";
 
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Html,
+    #[serde(alias = "md")]
+    Markdown,
+}
+impl Format {
+    pub fn from_file(file: &str) -> Self {
+        if file.ends_with(".md") {
+            Self::Markdown
+        } else {
+            Self::Html
+        }
+    }
+    // Convert text already rendered by Tera into HTML
+    fn convert(&self, text: String) -> String {
+        match self {
+            Self::Html => text,
+            Self::Markdown => {
+                let mut options = Options::empty();
+                options.insert(Options::ENABLE_TABLES);
+                options.insert(Options::ENABLE_FOOTNOTES);
+                options.insert(Options::ENABLE_STRIKETHROUGH);
+                options.insert(Options::ENABLE_TASKLISTS);
+                let mut html_text = String::new();
+                html::push_html(&mut html_text, Parser::new_ext(&text, options));
+                html_text
+            },
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
 pub enum PageBuilder {
     Redirect { path: String, url: String },
-    WithoutBody { path: String, context: Context },
-    TextBody { path: String, context: Context, body: String },
-    FileBody { path: String, context: Context, body: String },
+    WithoutBody { path: String, context: Context, format: Format, template: Option<String> },
+    TextBody { path: String, context: Context, body: String, format: Format, template: Option<String> },
+    FileBody { path: String, context: Context, file: String, body: String, format: Format, template: Option<String> },
+    TemplateBody { path: String, context: Context, template: String },
 }
 impl PageBuilder {
     pub fn path(&self) -> &str {
         match self {
-            Self::Redirect{ path, url:_ } => &path,
-            Self::WithoutBody{ path, context:_ } => &path,
-            Self::TextBody{ path, context:_, body:_ } => &path,
-            Self::FileBody{ path, context:_, body:_ } => &path,
+            Self::Redirect{ path, .. } => path,
+            Self::WithoutBody{ path, .. } => path,
+            Self::TextBody{ path, .. } => path,
+            Self::FileBody{ path, .. } => path,
+            Self::TemplateBody{ path, .. } => path,
+        }
+    }
+    pub fn context(&self) -> Option<&Context> {
+        match self {
+            Self::Redirect{ .. } => None,
+            Self::WithoutBody{ context, .. } => Some(context),
+            Self::TextBody{ context, .. } => Some(context),
+            Self::FileBody{ context, .. } => Some(context),
+            Self::TemplateBody{ context, .. } => Some(context),
+        }
+    }
+    pub fn template(&self) -> Option<&str> {
+        match self {
+            Self::Redirect{ .. } => None,
+            Self::WithoutBody{ template, .. } => template.as_deref(),
+            Self::TextBody{ template, .. } => template.as_deref(),
+            Self::FileBody{ template, .. } => template.as_deref(),
+            Self::TemplateBody{ template, .. } => Some(template),
+        }
+    }
+    pub fn body(&self) -> Option<&str> {
+        match self {
+            Self::TextBody{ body, .. } => Some(body),
+            Self::FileBody{ body, .. } => Some(body),
+            _ => None,
         }
     }
     pub fn redirect(path: &str, url: &str) -> Self {
@@ -35,73 +100,164 @@ impl PageBuilder {
             url: url.to_string(),
         }
     }
-    pub fn no_body(path: &str, context: Context) -> Self {
+    pub fn no_body(path: &str, context: Context, format: Format, template: Option<String>) -> Self {
         Self::WithoutBody {
             path: path.to_string(),
-            context: context,
+            context,
+            format,
+            template,
         }
     }
-    pub fn text_body(path: &str, context: Context, body: &str) -> Self {
+    pub fn text_body(path: &str, context: Context, body: &str, format: Format, template: Option<String>) -> Self {
         Self::TextBody {
             path: path.to_string(),
-            context: context,
+            context,
             body: body.to_string(),
+            format,
+            template,
         }
     }
-    pub fn file_body(path: &str, body: &str) -> Self {
-        Self::FileBody {
+    pub fn file_body(path: &str, body_file: &str, pages: &Path) -> Result<Self> {
+        let mut context = Context::new();
+        let mut template = None;
+        let body = read_page_file(pages, body_file, &mut context, &mut template)?;
+        Ok(Self::FileBody {
             path: path.to_string(),
-            context: Context::new(),
-            body: body.to_string(),
+            context,
+            file: body_file.to_string(),
+            body,
+            format: Format::from_file(body_file),
+            template,
+        })
+    }
+    pub fn template_body(path: &str, context: Context, template: &str) -> Self {
+        Self::TemplateBody {
+            path: path.to_string(),
+            context,
+            template: template.to_string(),
         }
     }
-    pub fn add_file_body(&mut self, body_file: &str) -> Result<()> {
+    // Copy a page to a new path with a paginator in its context
+    pub fn paginated(&self, new_path: &str, paginator: &tera::Value) -> Self {
+        let mut page = self.clone();
+        match &mut page {
+            Self::Redirect{ path, .. } => *path = new_path.to_string(),
+            Self::WithoutBody{ path, context, .. } | Self::TextBody{ path, context, .. }
+                    | Self::FileBody{ path, context, .. } | Self::TemplateBody{ path, context, .. } => {
+                *path = new_path.to_string();
+                context.insert("paginator", paginator);
+            },
+        }
+        page
+    }
+    pub fn add_file_body(&mut self, body_file: &str, pages: &Path) -> Result<()> {
         match self {
-            Self::Redirect{ path, url:_ } => bail!("Page file conflicts with redirect '{}'", path),
-            Self::WithoutBody{ path, context } => {
-                *self = Self::FileBody { path: path.clone(), context: context.clone(), body: body_file.to_string() };
+            Self::Redirect{ path, .. } => bail!("Page file conflicts with redirect '{}'", path),
+            Self::WithoutBody{ path, context, format, template } => {
+                let format = match Format::from_file(body_file) {
+                    Format::Markdown => Format::Markdown,
+                    Format::Html => *format,
+                };
+                let mut context = context.clone();
+                let mut template = template.clone();
+                let body = read_page_file(pages, body_file, &mut context, &mut template)?;
+                *self = Self::FileBody { path: path.clone(), context, file: body_file.to_string(), body, format, template };
             },
-            Self::TextBody{ path, context:_, body:_ } => bail!("Conflicting bodies for page '{}'", path),
-            Self::FileBody{ path, context:_, body:_ } => bail!("Conflicting files for page '{}'", path),
-
+            Self::TextBody{ path, .. } | Self::FileBody{ path, .. } | Self::TemplateBody{ path, .. } =>
+                bail!("Page '{}' already has a body", path),
         }
         Ok(())
     }
-    pub fn build(&self, tera: &mut Tera, global: &Context, output: &Path, pages: &Path) -> Result<()> {
-        let text = self.render(tera, global, pages)?;
+    pub fn build(&self, tera: &mut Tera, shared: &mut Context, default_template: Option<&str>, output: &Path) -> Result<()> {
+        let text = self.render(tera, shared, default_template)?;
         //println!("Page: {}", text);
-        let full_path = output.join(self.path());
-        match full_path.parent() {
-            Some(p) => fs::create_dir_all(p)?,
-            None => (),
-        };
-        let mut file = fs::File::create(full_path).unwrap();
-        file.write(text.as_bytes())?;
-        Ok(())
+        crate::write_file(&output.join(self.path()), &text)
+    }
+    // Render with the page's values added to the shared context, which is restored afterwards
+    // so the site is not copied for every page
+    fn render(&self, tera: &mut Tera, shared: &mut Context, default_template: Option<&str>) -> Result<String> {
+        let mut values = Context::new();
+        values.insert("page", &site::metadata(self));
+        if let Some(c) = self.context() {
+            values.extend(c.clone());
+        }
+        // Layout templates also add 'content'
+        let mut saved = vec![("content".to_string(), shared.remove("content"))];
+        if let Value::Object(values) = values.into_json() {
+            for (key, value) in values {
+                saved.push((key.clone(), shared.remove(&key)));
+                shared.insert(key, &value);
+            }
+        }
+        let result = self.render_body(tera, shared, default_template);
+        for (key, value) in saved.into_iter().rev() {
+            match value {
+                Some(v) => shared.insert(key, &v),
+                None => {
+                    shared.remove(&key);
+                },
+            }
+        }
+        result
     }
-    fn render(&self, tera: &mut Tera, global: &Context, pages: &Path) -> Result<String> {
-        let mut global_context = global.clone();
+    fn render_body(&self, tera: &mut Tera, context: &mut Context, default_template: Option<&str>) -> Result<String> {
         match self {
-            Self::Redirect{ path:_, url } => {
+            Self::Redirect{ url, .. } => {
                 let mut context = Context::new();
                 context.insert("url", url);
                 Ok(tera.render_str(REDIRECT_TEMPLATE, &context)?)
             },
-            Self::WithoutBody{ path, context:_ } => Err(anyhow!("Missing body for page '{}'", path)),
-            Self::TextBody{ path:_, context, body } => {
-                global_context.extend(context.clone());
-                Ok(tera.render_str(body, &global_context)?)
+            Self::WithoutBody{ path, .. } => Err(anyhow!("Missing body for page '{}'", path)),
+            Self::TextBody{ body, format, template, .. } => {
+                let text = format.convert(tera.render_str(body, context)?);
+                apply_template(tera, context, template.as_deref().or(default_template), text)
             },
-            Self::FileBody{ path:_, context, body } => {
-                let mut file = fs::File::open(pages.join(&body))?;
-                let mut contents = String::new();
-                file.read_to_string(&mut contents)?;
-                //println!("Content: {}", contents);
-                global_context.extend(context.clone());
-                Ok(tera.render_str(&contents, &global_context)?)
+            Self::FileBody{ file, body, format, template, .. } => {
+                let text = tera.render_str(body, context)
+                    .with_context(|| format!("Failed to render page file '{}'", file))?;
+                apply_template(tera, context, template.as_deref().or(default_template), format.convert(text))
+            },
+            Self::TemplateBody{ template, .. } => {
+                tera.render(template, context).with_context(|| format!("Failed to render template '{}'", template))
             },
         }
     }
 }
 
 
+// Wrap a rendered page body in its layout template, an empty template name disables the layout
+fn apply_template(tera: &Tera, context: &mut Context, template: Option<&str>, content: String) -> Result<String> {
+    match template {
+        Some(t) if !t.is_empty() => {
+            context.insert("content", &content);
+            tera.render(t, context).with_context(|| format!("Failed to render layout template '{}'", t))
+        },
+        _ => Ok(content),
+    }
+}
+
+// Read a page file, adding any front matter to the page's context and template
+fn read_page_file(pages: &Path, body_file: &str, context: &mut Context, template: &mut Option<String>) -> Result<String> {
+    let mut file = fs::File::open(pages.join(body_file))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    //println!("Content: {}", contents);
+    let (matter, body) = frontmatter::split(&contents)
+        .with_context(|| format!("Failed to read front matter of page file '{}'", body_file))?;
+    if let Some(t) = matter.get("template") {
+        match t.as_str() {
+            Some(s) => *template = Some(s.to_string()),
+            None => bail!("Template in front matter of page file '{}' must be a string", body_file),
+        }
+    }
+    context.extend(matter);
+    Ok(body.to_string())
+}
+
+// Determine the site path of a page file, Markdown files become HTML pages
+pub fn file_page_path(file: &str) -> String {
+    match file.strip_suffix(".md") {
+        Some(stem) => stem.to_string() + ".html",
+        None => file.to_string(),
+    }
+}
@@ -0,0 +1,129 @@
+// Terafirma static site generator
+//   Penn Bauman <me@pennbauman.com>
+use clap::ValueEnum;
+use serde_json::json;
+use terafirma::{BuildErrors, BuildSummary, PageError};
+
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MessageFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+// Prints progress and errors, either for people or as one JSON object per line for scripts
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Reporter {
+    pub format: MessageFormat,
+    pub quiet: bool,
+    pub verbose: bool,
+}
+impl Reporter {
+    // Status messages such as 'Check: Terafirma.toml is valid'
+    pub fn message(&self, kind: &str, text: &str) {
+        if self.quiet {
+            return;
+        }
+        match self.format {
+            MessageFormat::Human => println!("{}: {}", kind, text),
+            MessageFormat::Json => println!("{}", json!({ "type": kind.to_lowercase(), "message": text })),
+        }
+    }
+    // Warnings are printed to stderr, unless quiet
+    pub fn warning(&self, text: &str) {
+        if self.quiet {
+            return;
+        }
+        match self.format {
+            MessageFormat::Human => eprintln!("Warning: {}", text),
+            MessageFormat::Json => println!("{}", json!({ "type": "warning", "message": text })),
+        }
+    }
+    pub fn build(&self, summary: &BuildSummary) {
+        if self.quiet {
+            return;
+        }
+        if self.verbose {
+            let outputs = [("Rendered", &summary.pages), ("Copied", &summary.static_files),
+                ("Redirected", &summary.redirects), ("Removed", &summary.removed)];
+            for (action, paths) in outputs {
+                for p in paths {
+                    match self.format {
+                        MessageFormat::Human => println!("  {} {}", action, p),
+                        MessageFormat::Json => println!("{}", json!({ "type": "output", "action": action.to_lowercase(), "path": p })),
+                    }
+                }
+            }
+        }
+        match self.format {
+            MessageFormat::Human => {
+                let mut text = format!("rendered {}, copied {} and wrote {} in {:.2}s",
+                    count(summary.pages.len(), "page"), count(summary.static_files.len(), "static file"),
+                    count(summary.redirects.len(), "redirect"), summary.elapsed.as_secs_f64());
+                if summary.unchanged > 0 {
+                    text += &format!(" ({} unchanged)", summary.unchanged);
+                }
+                println!("Build: {}", text);
+            },
+            MessageFormat::Json => println!("{}", json!({
+                "type": "build",
+                "pages": summary.pages.len(),
+                "static_files": summary.static_files.len(),
+                "redirects": summary.redirects.len(),
+                "removed": summary.removed.len(),
+                "unchanged": summary.unchanged,
+                "elapsed": summary.elapsed.as_secs_f64(),
+            })),
+        }
+    }
+    // Errors are printed with every cause, even when quiet
+    pub fn error(&self, error: &anyhow::Error) {
+        if let Some(errors) = error.downcast_ref::<BuildErrors>() {
+            for e in &errors.pages {
+                self.page_error(e);
+            }
+        } else if let Some(e) = error.downcast_ref::<PageError>() {
+            return self.page_error(e);
+        }
+        match self.format {
+            MessageFormat::Human => {
+                eprintln!("Error: {}", error);
+                for cause in error.chain().skip(1) {
+                    eprintln!("  Caused by: {}", cause);
+                }
+            },
+            MessageFormat::Json => {
+                let causes: Vec<String> = error.chain().skip(1).map(|c| c.to_string()).collect();
+                println!("{}", json!({ "type": "error", "message": error.to_string(), "causes": causes }));
+            },
+        }
+    }
+    fn page_error(&self, error: &PageError) {
+        match self.format {
+            MessageFormat::Human => {
+                eprintln!("Error: {} (from '{}')", error, error.file.display());
+                for cause in error.error.chain() {
+                    eprintln!("  Caused by: {}", cause);
+                }
+            },
+            MessageFormat::Json => {
+                let causes: Vec<String> = error.error.chain().map(|c| c.to_string()).collect();
+                println!("{}", json!({
+                    "type": "error",
+                    "message": error.to_string(),
+                    "path": error.path,
+                    "file": error.file,
+                    "causes": causes,
+                }));
+            },
+        }
+    }
+}
+
+fn count(n: usize, name: &str) -> String {
+    match n {
+        1 => format!("1 {}", name),
+        _ => format!("{} {}s", n, name),
+    }
+}
@@ -0,0 +1,188 @@
+// Terafirma static site generator
+//   Penn Bauman <me@pennbauman.com>
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use anyhow::Result;
+
+static RELOAD_PATH: &str = "/__terafirma/reload";
+static RELOAD_SCRIPT: &str = "<script>(function() {
+	var source = new EventSource(\"/__terafirma/reload\");
+	source.addEventListener(\"reload\", function() { location.reload(); });
+	source.addEventListener(\"css\", function() {
+		document.querySelectorAll(\"link[rel=stylesheet]\").forEach(function(link) {
+			var url = new URL(link.href);
+			url.searchParams.set(\"terafirma-reload\", Date.now());
+			link.href = url.href;
+		});
+	});
+})();</script>";
+
+
+// Tells connected browsers to reload after the site is rebuilt
+#[derive(Default)]
+pub struct LiveReload {
+    clients: Mutex<Vec<TcpStream>>,
+}
+impl LiveReload {
+    // Reload pages, or only their stylesheets if nothing else changed
+    pub fn notify(&self, changed: &[PathBuf]) {
+        let event = match changed.iter().all(|p| p.extension().is_some_and(|e| e == "css")) {
+            true => "css",
+            false => "reload",
+        };
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain_mut(|c| write!(c, "event: {}\ndata: {}\n\n", event, event).and_then(|_| c.flush()).is_ok());
+    }
+    fn add_client(&self, mut stream: TcpStream) -> Result<()> {
+        write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n")?;
+        stream.flush()?;
+        self.clients.lock().unwrap().push(stream);
+        Ok(())
+    }
+}
+
+
+// Serve files from a built site until the process is stopped
+pub fn serve(root: &Path, listener: TcpListener, reload: Option<Arc<LiveReload>>) -> Result<()> {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        let root = root.to_path_buf();
+        let reload = reload.clone();
+        thread::spawn(move || {
+            // Errors only affect a single connection
+            let _ = handle_connection(&root, stream, reload.as_deref());
+        });
+    }
+    Ok(())
+}
+
+fn handle_connection(root: &Path, mut stream: TcpStream, reload: Option<&LiveReload>) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Skip headers
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    if method != "GET" && method != "HEAD" {
+        return respond(&mut stream, method, "405 Method Not Allowed", "text/plain", b"Method not allowed\n");
+    }
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    if let Some(r) = reload {
+        if path == RELOAD_PATH {
+            return r.add_client(stream);
+        }
+    }
+    let (status, file) = match resolve(root, &percent_decode(path)) {
+        Some(file) => ("200 OK", file),
+        None => ("404 Not Found", root.join("404.html")),
+    };
+    let mut body = match fs::read(&file) {
+        Ok(b) => b,
+        Err(_) => return respond(&mut stream, method, status, "text/plain", b"Not found\n"),
+    };
+    let kind = content_type(&file);
+    if reload.is_some() && kind.starts_with("text/html") {
+        body = inject_script(body);
+    }
+    respond(&mut stream, method, status, kind, &body)
+}
+
+// Add the live reload script to the end of an HTML page's body
+fn inject_script(html: Vec<u8>) -> Vec<u8> {
+    let text = String::from_utf8_lossy(&html);
+    let pos = text.to_ascii_lowercase().rfind("</body>").unwrap_or(text.len());
+    let mut ret = text[..pos].to_string();
+    ret.push_str(RELOAD_SCRIPT);
+    ret.push_str(&text[pos..]);
+    ret.into_bytes()
+}
+
+fn respond(stream: &mut TcpStream, method: &str, status: &str, content_type: &str, body: &[u8]) -> Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        status, content_type, body.len())?;
+    if method != "HEAD" {
+        stream.write_all(body)?;
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+// Find the file for a URL path, '/foo' can refer to 'foo', 'foo.html' or 'foo/index.html'
+pub fn resolve(root: &Path, url_path: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for c in Path::new(url_path.trim_start_matches('/')).components() {
+        match c {
+            Component::Normal(p) => path.push(p),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+    if path.is_file() {
+        return Some(path);
+    }
+    let mut html = path.clone().into_os_string();
+    html.push(".html");
+    let html = PathBuf::from(html);
+    if !url_path.ends_with('/') && path != root && html.is_file() {
+        return Some(html);
+    }
+    let index = path.join("index.html");
+    if index.is_file() {
+        return Some(index);
+    }
+    None
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut ret = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(b) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                ret.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        ret.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&ret).to_string()
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).unwrap_or_default() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
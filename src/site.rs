@@ -0,0 +1,312 @@
+// Terafirma static site generator
+//   Penn Bauman <me@pennbauman.com>
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use anyhow::{bail, Result};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime};
+use tera::{Tera, Value, Map, to_value, try_get_value};
+use crate::page::PageBuilder;
+use crate::config::PaginateConfig;
+
+// Name of the section containing pages at the top of the site
+const ROOT_SECTION: &str = "/";
+
+// Collect metadata for all pages, grouped into sections by directory
+pub fn collect(pages: &[PageBuilder]) -> Value {
+    let mut all = vec![];
+    let mut sections: BTreeMap<String, (Vec<Value>, Vec<String>)> = BTreeMap::new();
+    sections.insert(ROOT_SECTION.to_string(), (vec![], vec![]));
+    let mut sorted: Vec<&PageBuilder> = pages.iter().filter(|p| p.context().is_some()).collect();
+    sorted.sort_by(|a, b| a.path().cmp(b.path()));
+    for p in sorted {
+        let meta = metadata(p);
+        let mut dir = section_of(p.path());
+        sections.entry(dir.clone()).or_default().0.push(meta.clone());
+        // Add parent sections, even if they contain no pages directly
+        while dir != ROOT_SECTION {
+            let parent = section_of(&dir);
+            let subsections = &mut sections.entry(parent.clone()).or_default().1;
+            if !subsections.contains(&dir) {
+                subsections.push(dir);
+            }
+            dir = parent;
+        }
+        all.push(meta);
+    }
+
+    let mut section_map = Map::new();
+    for (path, (pages, mut subsections)) in sections {
+        subsections.sort();
+        let mut s = Map::new();
+        s.insert("url".to_string(), Value::String(section_url(&path)));
+        s.insert("path".to_string(), Value::String(path.clone()));
+        s.insert("pages".to_string(), Value::Array(pages));
+        s.insert("subsections".to_string(), to_value(subsections).unwrap());
+        section_map.insert(path, Value::Object(s));
+    }
+    let mut site = Map::new();
+    site.insert("pages".to_string(), Value::Array(all));
+    site.insert("sections".to_string(), Value::Object(section_map));
+    Value::Object(site)
+}
+
+// Metadata describing a single page
+pub fn metadata(page: &PageBuilder) -> Value {
+    let context = match page.context() {
+        Some(c) => c.clone().into_json(),
+        None => Value::Object(Map::new()),
+    };
+    let mut meta = Map::new();
+    meta.insert("path".to_string(), Value::String(format!("/{}", page.path())));
+    meta.insert("url".to_string(), Value::String(page_url(page.path())));
+    meta.insert("section".to_string(), Value::String(section_of(page.path())));
+    for key in ["title", "date", "weight"] {
+        meta.insert(key.to_string(), context.get(key).cloned().unwrap_or(Value::Null));
+    }
+    meta.insert("context".to_string(), context);
+    Value::Object(meta)
+}
+
+// Split a section's pages into the paginators for each page of a paginated page
+pub fn paginate(site: &Value, path: &str, config: &PaginateConfig) -> Result<Vec<(String, Value)>> {
+    let section = match site["sections"].get(&config.section) {
+        Some(s) => s,
+        None => bail!("Unknown section '{}' for pagination of page '/{}'", config.section, path),
+    };
+    let own_path = format!("/{}", path);
+    let mut items: Vec<Value> = section["pages"].as_array().unwrap().iter()
+        .filter(|p| p["path"] != own_path.as_str())
+        .cloned()
+        .collect();
+    if let Some(key) = &config.sort_by {
+        sort_page_list(&mut items, key, config.reverse);
+    } else if config.reverse {
+        items.reverse();
+    }
+
+    // Determine paths for each page, the first page keeps the original path
+    let dir = section_of(path);
+    let total = items.len().div_ceil(config.per_page).max(1);
+    let paths: Vec<String> = (1..=total).map(|n| match n {
+        1 => path.to_string(),
+        _ if dir == ROOT_SECTION => format!("page/{}/index.html", n),
+        _ => format!("{}/page/{}/index.html", dir, n),
+    }).collect();
+    let urls: Vec<String> = paths.iter().map(|p| page_url(p)).collect();
+
+    let mut ret = vec![];
+    for (i, chunk) in items.chunks(config.per_page).chain(std::iter::once(&[][..])).take(total).enumerate() {
+        let mut pg = Map::new();
+        pg.insert("pages".to_string(), Value::Array(chunk.to_vec()));
+        pg.insert("current".to_string(), Value::from(i + 1));
+        pg.insert("total_pages".to_string(), Value::from(total));
+        pg.insert("per_page".to_string(), Value::from(config.per_page));
+        pg.insert("total_items".to_string(), Value::from(items.len()));
+        pg.insert("urls".to_string(), to_value(&urls).unwrap());
+        pg.insert("first".to_string(), Value::String(urls[0].clone()));
+        pg.insert("last".to_string(), Value::String(urls[total - 1].clone()));
+        pg.insert("previous".to_string(), match i {
+            0 => Value::Null,
+            _ => Value::String(urls[i - 1].clone()),
+        });
+        pg.insert("next".to_string(), match urls.get(i + 1) {
+            Some(u) => Value::String(u.clone()),
+            None => Value::Null,
+        });
+        ret.push((paths[i].clone(), Value::Object(pg)));
+    }
+    Ok(ret)
+}
+
+// Get the URL of a page, index pages are referred to by their directory
+pub fn page_url(path: &str) -> String {
+    match path.strip_suffix("index.html") {
+        Some(dir) if dir.is_empty() || dir.ends_with('/') => format!("/{}", dir),
+        _ => format!("/{}", path),
+    }
+}
+
+// Join a site URL such as '/posts/' to the base URL the site is hosted at
+pub fn absolute_url(base_url: &str, url: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), url.trim_start_matches('/'))
+}
+
+// Parse a page date, times without an offset and dates without a time are treated as UTC
+pub fn parse_date(text: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(d) = DateTime::parse_from_rfc3339(text) {
+        return Some(d);
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"] {
+        if let Ok(d) = NaiveDateTime::parse_from_str(text, format) {
+            return Some(d.and_utc().fixed_offset());
+        }
+    }
+    NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()
+        .map(|d| d.and_hms_opt(0, 0, 0).unwrap().and_utc().fixed_offset())
+}
+
+// Get the section (directory) a page path belongs to
+fn section_of(path: &str) -> String {
+    match path.rsplit_once('/') {
+        Some((dir, _)) => dir.to_string(),
+        None => ROOT_SECTION.to_string(),
+    }
+}
+
+fn section_url(path: &str) -> String {
+    if path == ROOT_SECTION {
+        path.to_string()
+    } else {
+        format!("/{}/", path)
+    }
+}
+
+
+// Register filters for working with lists of pages
+pub fn register_filters(tera: &mut Tera) {
+    tera.register_filter("sort_pages", sort_pages);
+    tera.register_filter("filter_pages", filter_pages);
+}
+
+// Register functions for finding and linking to pages, relative to where the site is hosted
+pub fn register_functions(tera: &mut Tera, base_url: Option<&str>, site: &Value) {
+    let prefix = base_url.map(base_path).unwrap_or_default();
+    tera.register_function("url_for", move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+        let path = get_path_arg("url_for", args)?;
+        Ok(Value::String(format!("{}{}", prefix, page_url(&path))))
+    });
+    let base_url = base_url.map(|u| u.to_string());
+    tera.register_function("absolute_url", move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+        let path = get_path_arg("absolute_url", args)?;
+        match &base_url {
+            Some(b) => Ok(Value::String(absolute_url(b, &page_url(&path)))),
+            None => Err(tera::Error::msg("The `absolute_url` function requires base_url to be set")),
+        }
+    });
+    let pages = site["pages"].clone();
+    tera.register_function("get_page", move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+        let path = format!("/{}", get_path_arg("get_page", args)?);
+        let found = pages.as_array().and_then(|p| p.iter().find(|p| p["path"] == path.as_str() || p["url"] == path.as_str()));
+        match found {
+            Some(p) => Ok(p.clone()),
+            None => Err(tera::Error::msg(format!("Page '{}' not found", path))),
+        }
+    });
+    let sections = site["sections"].clone();
+    tera.register_function("get_section", move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+        // Sections are named by directory, so '/posts/' and 'posts' are the same section
+        let path = get_path_arg("get_section", args)?;
+        let name = match path.trim_end_matches('/') {
+            "" => ROOT_SECTION,
+            p => p,
+        };
+        match sections.get(name) {
+            Some(s) => Ok(s.clone()),
+            None => Err(tera::Error::msg(format!("Section '{}' not found", name))),
+        }
+    });
+}
+
+// Path the site is hosted under, such as '/docs' for 'https://example.com/docs/'
+fn base_path(base_url: &str) -> String {
+    let path = match base_url.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("", |i| &rest[i..]),
+        None => base_url,
+    };
+    path.trim_end_matches('/').to_string()
+}
+
+// Page path argument of a function, with any leading '/' removed
+fn get_path_arg(name: &str, args: &HashMap<String, Value>) -> tera::Result<String> {
+    match args.get("path") {
+        Some(Value::String(p)) => Ok(p.trim_start_matches('/').to_string()),
+        Some(_) => Err(tera::Error::msg(format!("The `{}` function argument `path` must be a string", name))),
+        None => Err(tera::Error::msg(format!("The `{}` function has to have a `path` argument", name))),
+    }
+}
+
+// Filter to sort pages by 'date', 'title', 'weight' or 'path'
+fn sort_pages(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let mut pages = try_get_value!("sort_pages", "value", Vec<Value>, value);
+    let key = match args.get("by") {
+        Some(v) => try_get_value!("sort_pages", "by", String, v),
+        None => "path".to_string(),
+    };
+    let reverse = match args.get("reverse") {
+        Some(v) => try_get_value!("sort_pages", "reverse", bool, v),
+        None => false,
+    };
+    sort_page_list(&mut pages, &key, reverse);
+    Ok(Value::Array(pages))
+}
+
+// Sort pages by a metadata or context value, pages missing the value are placed last
+pub fn sort_page_list(pages: &mut [Value], key: &str, reverse: bool) {
+    pages.sort_by(|a, b| {
+        match (page_value(a, key), page_value(b, key)) {
+            (Some(x), Some(y)) => {
+                let order = match key {
+                    "date" => compare_dates(x, y),
+                    _ => compare_values(x, y),
+                };
+                if reverse { order.reverse() } else { order }
+            },
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    });
+}
+
+// Keep pages with a context value matching 'value', or any truthy value if unspecified
+fn filter_pages(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let pages = try_get_value!("filter_pages", "value", Vec<Value>, value);
+    let key = match args.get("key") {
+        Some(v) => try_get_value!("filter_pages", "key", String, v),
+        None => return Err(tera::Error::msg("The `filter_pages` filter has to have a `key` argument")),
+    };
+    let wanted = args.get("value");
+    let ret = pages.into_iter().filter(|p| {
+        let found = match page_value(p, &key) {
+            Some(v) => v,
+            None => return false,
+        };
+        match (wanted, found) {
+            (Some(w), Value::Array(a)) => a.contains(w),
+            (Some(w), v) => v == w,
+            (None, Value::Bool(b)) => *b,
+            (None, _) => true,
+        }
+    }).collect();
+    Ok(Value::Array(ret))
+}
+
+// Find a value in a page's metadata, falling back to its context
+fn page_value<'a>(page: &'a Value, key: &str) -> Option<&'a Value> {
+    match page.get(key) {
+        Some(Value::Null) | None => match page.get("context").and_then(|c| c.get(key)) {
+            Some(Value::Null) | None => None,
+            v => v,
+        },
+        v => v,
+    }
+}
+
+// Dates in different formats or time zones are compared as times, when they can be parsed
+fn compare_dates(a: &Value, b: &Value) -> Ordering {
+    match (a.as_str().and_then(parse_date), b.as_str().and_then(parse_date)) {
+        (Some(x), Some(y)) => x.cmp(&y),
+        _ => compare_values(a, b),
+    }
+}
+
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => {
+            x.as_f64().partial_cmp(&y.as_f64()).unwrap_or(Ordering::Equal)
+        },
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        _ => a.to_string().cmp(&b.to_string()),
+    }
+}
@@ -0,0 +1,154 @@
+// Terafirma static site generator
+//   Penn Bauman <me@pennbauman.com>
+use std::fmt::Write;
+use anyhow::{bail, Result};
+use chrono::{NaiveDate, SecondsFormat};
+use tera::Value;
+use crate::page::PageBuilder;
+use crate::site;
+
+// Most URLs search engines accept in a single sitemap
+const MAX_URLS: usize = 50_000;
+const FREQUENCIES: [&str; 7] = ["always", "hourly", "daily", "weekly", "monthly", "yearly", "never"];
+static XMLNS: &str = "http://www.sitemaps.org/schemas/sitemap/0.9";
+
+
+#[derive(Debug)]
+struct Entry {
+    loc: String,
+    lastmod: Option<String>,
+    changefreq: Option<String>,
+    priority: Option<f64>,
+}
+
+// Lists every rendered page for search engines
+#[derive(Debug)]
+pub struct Sitemap {
+    path: String,
+}
+impl Sitemap {
+    pub fn new(path: String) -> Self {
+        Self { path }
+    }
+    // Paths and contents of the sitemap files, large sites get an index listing several sitemaps
+    pub fn files(&self, base_url: &str, pages: &[&PageBuilder]) -> Result<Vec<(String, String)>> {
+        self.split_files(base_url, pages, MAX_URLS)
+    }
+    fn split_files(&self, base_url: &str, pages: &[&PageBuilder], limit: usize) -> Result<Vec<(String, String)>> {
+        let mut entries = vec![];
+        for page in pages {
+            entries.extend(entry(base_url, page)?);
+        }
+        entries.sort_by(|a, b| a.loc.cmp(&b.loc));
+        if entries.len() <= limit {
+            return Ok(vec![(self.path.clone(), url_set(&entries))]);
+        }
+        // Number the sitemaps after the index, 'sitemap.xml' lists 'sitemap-1.xml', 'sitemap-2.xml' ...
+        let (stem, extension) = match self.path.rsplit_once('.') {
+            Some((s, e)) if !e.contains('/') => (s, format!(".{}", e)),
+            _ => (self.path.as_str(), String::new()),
+        };
+        let mut files = vec![];
+        let mut index = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<sitemapindex xmlns=\"{}\">\n", XMLNS);
+        for (i, chunk) in entries.chunks(limit).enumerate() {
+            let path = format!("{}-{}{}", stem, i + 1, extension);
+            let loc = site::absolute_url(base_url, &format!("/{}", path));
+            writeln!(index, "\t<sitemap>\n\t\t<loc>{}</loc>\n\t</sitemap>", xml_escape(&loc)).unwrap();
+            files.push((path, url_set(chunk)));
+        }
+        index.push_str("</sitemapindex>\n");
+        files.insert(0, (self.path.clone(), index));
+        Ok(files)
+    }
+}
+
+
+// Read a page's sitemap options, redirects and excluded pages have no entry
+fn entry(base_url: &str, page: &PageBuilder) -> Result<Option<Entry>> {
+    let context = match page.context() {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+    let options = context.get("sitemap").cloned().unwrap_or(Value::Null);
+    if !options.is_null() && !options.is_object() {
+        bail!("Sitemap options for page '/{}' must be a table", page.path());
+    }
+    match options.get("exclude") {
+        Some(Value::Bool(true)) => return Ok(None),
+        None | Some(Value::Bool(false)) => (),
+        Some(_) => bail!("Sitemap exclude for page '/{}' must be true or false", page.path()),
+    }
+    // The page's date is used if it does not set when it was last modified
+    let lastmod = match options.get("lastmod").or(context.get("date")) {
+        None | Some(Value::Null) => None,
+        // Dates are kept as they are, times are written as W3C datetimes like in feeds
+        Some(Value::String(s)) => match (NaiveDate::parse_from_str(s, "%Y-%m-%d"), site::parse_date(s)) {
+            (Ok(_), _) => Some(s.clone()),
+            (_, Some(d)) => Some(d.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            _ => bail!("Invalid sitemap lastmod '{}' for page '/{}', it must be a date such as '2023-06-01'", s, page.path()),
+        },
+        Some(_) => bail!("Sitemap lastmod for page '/{}' must be a date", page.path()),
+    };
+    let changefreq = match options.get("changefreq") {
+        None => None,
+        Some(Value::String(s)) if FREQUENCIES.contains(&s.as_str()) => Some(s.clone()),
+        Some(_) => bail!("Sitemap changefreq for page '/{}' must be one of {}", page.path(), FREQUENCIES.join(", ")),
+    };
+    let priority = match options.get("priority") {
+        None => None,
+        Some(v) => match v.as_f64() {
+            Some(p) if (0.0..=1.0).contains(&p) => Some(p),
+            _ => bail!("Sitemap priority for page '/{}' must be a number from 0.0 to 1.0", page.path()),
+        },
+    };
+    Ok(Some(Entry {
+        loc: site::absolute_url(base_url, &site::page_url(page.path())),
+        lastmod,
+        changefreq,
+        priority,
+    }))
+}
+
+fn url_set(entries: &[Entry]) -> String {
+    let mut ret = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"{}\">\n", XMLNS);
+    for e in entries {
+        writeln!(ret, "\t<url>\n\t\t<loc>{}</loc>", xml_escape(&e.loc)).unwrap();
+        if let Some(l) = &e.lastmod {
+            writeln!(ret, "\t\t<lastmod>{}</lastmod>", xml_escape(l)).unwrap();
+        }
+        if let Some(c) = &e.changefreq {
+            writeln!(ret, "\t\t<changefreq>{}</changefreq>", c).unwrap();
+        }
+        if let Some(p) = e.priority {
+            writeln!(ret, "\t\t<priority>{}</priority>", p).unwrap();
+        }
+        ret.push_str("\t</url>\n");
+    }
+    ret.push_str("</urlset>\n");
+    ret
+}
+
+pub fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+
+#[test]
+fn split_large_sitemaps() {
+    let pages: Vec<PageBuilder> = ["a.html", "b.html", "c.html"].iter()
+        .map(|p| PageBuilder::text_body(p, tera::Context::new(), "", Default::default(), None))
+        .collect();
+    let pages: Vec<&PageBuilder> = pages.iter().collect();
+    let sitemap = Sitemap::new("sitemap.xml".to_string());
+    let files = sitemap.split_files("https://example.com/", &pages, 2).unwrap();
+    let paths: Vec<&str> = files.iter().map(|(p, _)| p.as_str()).collect();
+    assert!(paths == ["sitemap.xml", "sitemap-1.xml", "sitemap-2.xml"]);
+    assert!(files[0].1.contains("<loc>https://example.com/sitemap-2.xml</loc>"));
+    assert!(files[1].1.contains("<loc>https://example.com/b.html</loc>"));
+    assert!(files[2].1.contains("<loc>https://example.com/c.html</loc>"));
+    assert!(sitemap.split_files("https://example.com", &pages, 3).unwrap().len() == 1);
+}
@@ -0,0 +1,125 @@
+// Terafirma static site generator
+//   Penn Bauman <me@pennbauman.com>
+use std::collections::BTreeMap;
+use anyhow::{bail, Result};
+use tera::{Context, Map, Value};
+use crate::config::TaxonomyConfig;
+use crate::page::PageBuilder;
+use crate::site;
+
+
+#[derive(Debug)]
+pub struct Taxonomy {
+    name: String,
+    path: String,
+    term_template: String,
+    list_template: Option<String>,
+}
+impl Taxonomy {
+    pub fn from_config(config: TaxonomyConfig, path: String) -> Self {
+        Self {
+            name: config.name,
+            path,
+            term_template: config.term_template,
+            list_template: config.list_template,
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn templates(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.term_template.as_str()).chain(self.list_template.as_deref())
+    }
+    // Group pages in the site by their terms for this taxonomy
+    pub fn collect(&self, site: &Value) -> Result<Value> {
+        let mut terms: BTreeMap<String, (String, Vec<Value>)> = BTreeMap::new();
+        for page in site["pages"].as_array().unwrap() {
+            let path = page["path"].as_str().unwrap_or_default();
+            let names = match page["context"].get(&self.name) {
+                None | Some(Value::Null) => continue,
+                Some(Value::String(s)) => vec![s.as_str()],
+                Some(Value::Array(a)) => {
+                    let mut names = vec![];
+                    for v in a {
+                        match v.as_str() {
+                            Some(s) => names.push(s),
+                            None => bail!("Terms of taxonomy '{}' for page '{}' must be strings", self.name, path),
+                        }
+                    }
+                    names
+                },
+                Some(_) => bail!("Terms of taxonomy '{}' for page '{}' must be strings", self.name, path),
+            };
+            for name in names {
+                let slug = slugify(name);
+                if slug.is_empty() {
+                    bail!("Term '{}' of taxonomy '{}' cannot be used in a URL", name, self.name);
+                }
+                let (first_name, pages) = terms.entry(slug.clone()).or_insert_with(|| (name.to_string(), vec![]));
+                if first_name != name {
+                    bail!("Terms '{}' and '{}' of taxonomy '{}' have the same URL", first_name, name, self.name);
+                }
+                pages.push(page.clone());
+            }
+        }
+
+        let mut term_list = vec![];
+        for (slug, (name, pages)) in terms {
+            let mut term = Map::new();
+            term.insert("name".to_string(), Value::String(name));
+            term.insert("path".to_string(), Value::String(format!("/{}", self.term_path(&slug))));
+            term.insert("url".to_string(), Value::String(site::page_url(&self.term_path(&slug))));
+            term.insert("slug".to_string(), Value::String(slug));
+            term.insert("pages".to_string(), Value::Array(pages));
+            term_list.push(Value::Object(term));
+        }
+        let mut taxonomy = Map::new();
+        taxonomy.insert("name".to_string(), Value::String(self.name.clone()));
+        taxonomy.insert("url".to_string(), Value::String(site::page_url(&self.list_path())));
+        taxonomy.insert("terms".to_string(), Value::Array(term_list));
+        Ok(Value::Object(taxonomy))
+    }
+    // Create the listing pages for a taxonomy collected from the site
+    pub fn pages(&self, taxonomy: &Value) -> Vec<PageBuilder> {
+        let mut ret = vec![];
+        if let Some(t) = &self.list_template {
+            let mut context = Context::new();
+            context.insert("taxonomy", taxonomy);
+            ret.push(PageBuilder::template_body(&self.list_path(), context, t));
+        }
+        for term in taxonomy["terms"].as_array().unwrap() {
+            let mut context = Context::new();
+            context.insert("taxonomy", taxonomy);
+            context.insert("term", term);
+            let path = self.term_path(term["slug"].as_str().unwrap());
+            ret.push(PageBuilder::template_body(&path, context, &self.term_template));
+        }
+        ret
+    }
+    fn list_path(&self) -> String {
+        match self.path.as_str() {
+            "" => "index.html".to_string(),
+            p => format!("{}/index.html", p.trim_end_matches('/')),
+        }
+    }
+    fn term_path(&self, slug: &str) -> String {
+        match self.path.as_str() {
+            "" => format!("{}/index.html", slug),
+            p => format!("{}/{}/index.html", p.trim_end_matches('/'), slug),
+        }
+    }
+}
+
+
+// Convert a term into a form usable in URLs
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.trim().chars().flat_map(|c| c.to_lowercase()) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
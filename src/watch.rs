@@ -0,0 +1,82 @@
+// Terafirma static site generator
+//   Penn Bauman <me@pennbauman.com>
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+static POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+
+// Watches files for changes by periodically checking their modification times
+pub struct Watcher {
+    paths: Vec<PathBuf>,
+    ignore: Vec<PathBuf>,
+    state: HashMap<PathBuf, (SystemTime, u64)>,
+}
+impl Watcher {
+    pub fn new(paths: Vec<PathBuf>, ignore: Vec<PathBuf>) -> Self {
+        let mut ret = Self { paths, ignore, state: HashMap::new() };
+        ret.state = ret.scan();
+        ret
+    }
+    // Block until files change, returning the changed paths
+    pub fn wait(&mut self) -> Vec<PathBuf> {
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let mut changed = self.changes();
+            if changed.is_empty() {
+                continue;
+            }
+            // Wait for a burst of changes (such as an editor saving) to settle
+            loop {
+                thread::sleep(POLL_INTERVAL);
+                let more = self.changes();
+                if more.is_empty() {
+                    break;
+                }
+                changed.extend(more);
+            }
+            changed.sort();
+            changed.dedup();
+            return changed;
+        }
+    }
+    fn changes(&mut self) -> Vec<PathBuf> {
+        let state = self.scan();
+        let mut changed: Vec<PathBuf> = state.iter()
+            .filter(|(p, s)| self.state.get(*p) != Some(s))
+            .map(|(p, _)| p.clone())
+            .collect();
+        changed.extend(self.state.keys().filter(|p| !state.contains_key(*p)).cloned());
+        self.state = state;
+        changed
+    }
+    fn scan(&self) -> HashMap<PathBuf, (SystemTime, u64)> {
+        let mut state = HashMap::new();
+        for p in &self.paths {
+            self.scan_path(p, &mut state);
+        }
+        state
+    }
+    fn scan_path(&self, path: &Path, state: &mut HashMap<PathBuf, (SystemTime, u64)>) {
+        if self.ignore.iter().any(|i| path.starts_with(i)) {
+            return;
+        }
+        let meta = match fs::metadata(path) {
+            Ok(m) => m,
+            Err(_) => return,
+        };
+        if meta.is_dir() {
+            if let Ok(entries) = fs::read_dir(path) {
+                for e in entries.flatten() {
+                    self.scan_path(&e.path(), state);
+                }
+            }
+        } else {
+            let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            state.insert(path.to_path_buf(), (modified, meta.len()));
+        }
+    }
+}
@@ -1,10 +1,14 @@
 // Terafirma system tests
 //   Penn Bauman <me@pennbauman.com>
 use std::fs;
-use std::io::Read;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
 use std::path::PathBuf;
 use std::env;
-use std::process::Command;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
 
 
 fn get_crate_exe() -> Option<PathBuf> {
@@ -16,7 +20,59 @@ fn get_crate_exe() -> Option<PathBuf> {
             return Some(buf);
         }
     }
-    return None;
+    None
+}
+
+fn http_get(addr: &str, path: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut stream = TcpStream::connect(addr)?;
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response)
+}
+
+// Child process that is stopped when dropped, even if a test fails
+struct Running(Child);
+impl Drop for Running {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// Read lines of output from a running process, including errors if they are piped
+fn read_lines(child: &mut Running) -> Receiver<String> {
+    let (sender, lines) = channel();
+    let stdout = child.0.stdout.take().unwrap();
+    let errors = sender.clone();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            if sender.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    if let Some(stderr) = child.0.stderr.take() {
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines() {
+                if errors.send(line.unwrap()).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    lines
+}
+
+// Wait for a line of output starting with a prefix, returning the line
+fn wait_for_line(lines: &Receiver<String>, prefix: &str) -> Option<String> {
+    loop {
+        match lines.recv_timeout(Duration::from_secs(20)) {
+            Ok(l) if l.starts_with(prefix) => return Some(l),
+            Ok(_) => (),
+            Err(_) => return None,
+        }
+    }
 }
 
 
@@ -31,7 +87,7 @@ fn test_files_only() -> Result<(), Box<dyn std::error::Error>> {
     assert!(root.join("output/img/emoticon.png").is_file());
     assert!(root.join("output/style.css").is_file());
     // Check pages
-    let mut file = fs::File::open(&root.join("output/home.html"))?;
+    let mut file = fs::File::open(root.join("output/home.html"))?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
     let expected = "<!DOCTYPE html><html lang=\"en\">
@@ -52,7 +108,7 @@ fn test_files_only() -> Result<(), Box<dyn std::error::Error>> {
 ";
     assert!(contents == expected);
 
-    let mut file = fs::File::open(&root.join("output/post-1.html"))?;
+    let mut file = fs::File::open(root.join("output/post-1.html"))?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
     let expected = "<!DOCTYPE html><html lang=\"en\">
@@ -74,7 +130,7 @@ fn test_files_only() -> Result<(), Box<dyn std::error::Error>> {
 ";
     assert!(contents == expected);
 
-    let mut file = fs::File::open(&root.join("output/post-2.html"))?;
+    let mut file = fs::File::open(root.join("output/post-2.html"))?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
     let expected = "<!DOCTYPE html><html lang=\"en\">
@@ -145,7 +201,7 @@ fn test_toml_files() -> Result<(), Box<dyn std::error::Error>> {
             .current_dir(&root)
             .status().is_ok());
     // Check pages
-    let mut file = fs::File::open(&root.join("output/post-1.html"))?;
+    let mut file = fs::File::open(root.join("output/post-1.html"))?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
     let expected = "<!DOCTYPE html><html lang=\"en\">
@@ -167,7 +223,7 @@ fn test_toml_files() -> Result<(), Box<dyn std::error::Error>> {
 ";
     assert!(contents == expected);
 
-    let mut file = fs::File::open(&root.join("output/post-2.html"))?;
+    let mut file = fs::File::open(root.join("output/post-2.html"))?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
     let expected = "<!DOCTYPE html><html lang=\"en\">
@@ -201,7 +257,7 @@ fn test_paths_changed() -> Result<(), Box<dyn std::error::Error>> {
     assert!(root.join("target/img/emoticon.png").is_file());
     assert!(root.join("target/style.css").is_file());
     // Check pages
-    let mut file = fs::File::open(&root.join("target/home.html"))?;
+    let mut file = fs::File::open(root.join("target/home.html"))?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
     let expected = "<!DOCTYPE html><html lang=\"en\">
@@ -222,7 +278,7 @@ fn test_paths_changed() -> Result<(), Box<dyn std::error::Error>> {
 ";
     assert!(contents == expected);
 
-    let mut file = fs::File::open(&root.join("target/post-1.html"))?;
+    let mut file = fs::File::open(root.join("target/post-1.html"))?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
     let expected = "<!DOCTYPE html><html lang=\"en\">
@@ -244,7 +300,7 @@ fn test_paths_changed() -> Result<(), Box<dyn std::error::Error>> {
 ";
     assert!(contents == expected);
 
-    let mut file = fs::File::open(&root.join("target/post-2.html"))?;
+    let mut file = fs::File::open(root.join("target/post-2.html"))?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
     let expected = "<!DOCTYPE html><html lang=\"en\">
@@ -264,7 +320,7 @@ fn test_paths_changed() -> Result<(), Box<dyn std::error::Error>> {
 ";
     assert!(contents == expected);
 
-    let mut file = fs::File::open(&root.join("target/github.html"))?;
+    let mut file = fs::File::open(root.join("target/github.html"))?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
     let expected = "<!DOCTYPE html><html>
@@ -275,3 +331,975 @@ fn test_paths_changed() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn test_markdown() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/markdown");
+    // Build site
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .status().is_ok());
+    // Check pages
+    assert!(!root.join("output/notes.md").exists());
+    let mut file = fs::File::open(root.join("output/notes.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let expected = "<h1>Notes for Terafirma</h1>
+<table><thead><tr><th>Feature</th><th>Done</th></tr></thead><tbody>
+<tr><td>Tables</td><td>yes</td></tr>
+</tbody></table>
+<ul>
+<li><input disabled=\"\" type=\"checkbox\" checked=\"\"/>
+Task lists</li>
+<li><input disabled=\"\" type=\"checkbox\"/>
+Footnotes<sup class=\"footnote-reference\"><a href=\"#1\">1</a></sup></li>
+</ul>
+<div class=\"footnote-definition\" id=\"1\"><sup class=\"footnote-definition-label\">1</sup>
+<p>Rendered at the end.</p>
+</div>
+";
+    assert!(contents == expected);
+
+    let mut file = fs::File::open(root.join("output/about.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let expected = "<h1>About Terafirma</h1>
+<p><del>Hand written</del> HTML is <em>optional</em>.</p>
+";
+    assert!(contents == expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_front_matter() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/front-matter");
+    // Build site
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .status().is_ok());
+    // Check pages
+    let mut file = fs::File::open(root.join("output/post.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let expected = "<h1>First Post</h1>
+<p>Posted 2023-06-01 by Nobody in news, rust.</p>
+";
+    assert!(contents == expected);
+
+    let mut file = fs::File::open(root.join("output/about.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let expected = "<h1>About</h1>
+<p>Penn</p>
+";
+    assert!(contents == expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_layouts() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/layouts");
+    // Build site
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .status().is_ok());
+    // Check pages
+    let mut file = fs::File::open(root.join("output/post.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let expected = "<!DOCTYPE html><html lang=\"en\">
+	<head>
+		<title>Layouts</title>
+	</head>
+	<body>
+<p>Pages can be <em>pure content</em>.</p>
+	</body>
+</html>
+";
+    assert!(contents == expected);
+
+    let mut file = fs::File::open(root.join("output/note.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    assert!(contents == "<div class=\"plain\"><p>A note</p>\n</div>\n");
+
+    let mut file = fs::File::open(root.join("output/plain.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    assert!(contents == "<div class=\"plain\"><p>Plain page</p></div>\n");
+
+    let mut file = fs::File::open(root.join("output/raw.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    assert!(contents == "<p>No layout</p>");
+
+    Ok(())
+}
+
+#[test]
+fn test_extra_keys() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/extra-keys");
+    // Build site
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .status().is_ok());
+    // Check pages
+    let mut file = fs::File::open(root.join("output/about.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    assert!(contents == "About: Extra keys");
+    // Keys overridden by the page's context are reported as warnings, which follow the message options
+    let build = |args: &[&str]| Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .args(["--config", "Overridden.toml"])
+        .args(args)
+        .output();
+    let output = build(&["build"])?;
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr)? == "Warning: 'title' for page '/about.html' is ignored, it is overridden by the page's context\n");
+    assert!(fs::read_to_string(root.join("output/about.html"))? == "Context");
+    let output = build(&["--quiet", "build"])?;
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    let output = build(&["--message-format", "json", "build"])?;
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    let stdout = String::from_utf8(output.stdout)?;
+    let warning: serde_json::Value = serde_json::from_str(stdout.lines().next().unwrap())?;
+    assert!(warning["type"] == "warning");
+    assert!(warning["message"] == "'title' for page '/about.html' is ignored, it is overridden by the page's context");
+
+    Ok(())
+}
+
+#[test]
+fn test_check() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/layouts");
+    let output = Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .arg("check")
+        .output()?;
+    assert!(output.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid_configs() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/invalid");
+    for config in ["settings-table.toml", "settings-key.toml", "page-format.toml", "redirect-key.toml",
+            "paginate-key.toml"] {
+        let output = Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .args(["--config", config])
+            .output()?;
+        assert!(!output.status.success(), "{} should fail", config);
+        let output = Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .args(["--config", config, "check"])
+            .output()?;
+        assert!(!output.status.success(), "{} should fail check", config);
+        assert!(String::from_utf8(output.stderr)?.contains("line"));
+    }
+    // Errors found while building
+    let output = Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .args(["--config", "generate-collision.toml"])
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("both have the path '/team/core.html'"));
+    // Paths outside the site
+    for (config, expected) in [("redirect-path.toml", "Invalid path '/docs/../../escape.html' for [[redirect]] 1 in 'redirect-path.toml'"),
+            ("page-path.toml", "Invalid path '/nul\0.html' for [[page]] 2 in 'page-path.toml'"),
+            ("taxonomy-name.toml", "Invalid name for [[taxonomy]] '../../escape' in 'taxonomy-name.toml'"),
+            ("taxonomy-path.toml", "Invalid path '/tags/../../escape/' for [[taxonomy]] 'tags' in 'taxonomy-path.toml'"),
+            ("page-dir.toml", "Invalid path '/posts/' for [[page]] 1 in 'page-dir.toml'\n  Caused by: Page path must end with a file name, such as '/posts/index.html'")] {
+        let output = Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .args(["--config", config, "check"])
+            .output()?;
+        assert!(!output.status.success(), "{} should fail check", config);
+        assert!(String::from_utf8(output.stderr)?.starts_with(&format!("Error: {}\n", expected)));
+    }
+    // Feeds
+    for (config, expected) in [("feed-format.toml", "Invalid [[feed]] 1 in 'feed-format.toml'\n  Caused by: Feed requires at least one of rss, atom or json\n"),
+            ("feed-author.toml", "Invalid [[feed]] 1 in 'feed-author.toml'\n  Caused by: Atom feeds require an author\n"),
+            ("feed-base-url.toml", "[[feed]] 'Posts' in 'feed-base-url.toml' requires base_url in [settings]\n"),
+            ("generate-source.toml", "Invalid [[generate]] 1 in 'generate-source.toml'\n  Caused by: Source '../page-path.toml' for generated pages '/{{ item }}.html' must be within the data directory\n")] {
+        let output = Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .args(["--config", config, "check"])
+            .output()?;
+        assert!(!output.status.success(), "{} should fail check", config);
+        assert!(String::from_utf8(output.stderr)? == format!("Error: {}", expected));
+    }
+    // Output directories containing the project are never deleted
+    for command in ["build", "clean"] {
+        let output = Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .args(["--config", "output-root.toml", command])
+            .output()?;
+        assert!(!output.status.success());
+        assert!(String::from_utf8(output.stderr)?.contains("contains the project directory"));
+        assert!(root.join("output-root.toml").is_file());
+    }
+    assert!(!root.join("output").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_collections() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/collections");
+    // Build site
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .status().is_ok());
+    // Check pages
+    let mut file = fs::File::open(root.join("output/index.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let expected = "<ul>
+	<li><a href=\"/posts/beta.html\">Beta</a> 2023-03-01</li>
+	<li><a href=\"/posts/alpha.html\">Alpha</a> 2023-01-01</li>
+	<li><a href=\"/posts/gamma.html\">Gamma</a> undated</li>
+</ul>
+<p>Tagged rust: Alpha Gamma</p>
+<p>Sections: /posts/</p>
+<p>This page: /</p>
+<p>Nav: /posts/beta.html /index.html /posts/alpha.html /posts/gamma.html</p>
+";
+    assert!(contents == expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_pagination() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/pagination");
+    // Build site
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .status().is_ok());
+    // Check pages
+    assert!(!root.join("output/posts/page/1").exists());
+    assert!(!root.join("output/posts/page/4").exists());
+    let mut file = fs::File::open(root.join("output/posts/index.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let expected = "<h1>Page 1 of 3</h1>
+<ul>
+	<li>Post 5</li>
+	<li>Post 4</li>
+</ul>
+<p>none | /posts/page/2/</p>
+";
+    assert!(contents == expected);
+
+    let mut file = fs::File::open(root.join("output/posts/page/2/index.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let expected = "<h1>Page 2 of 3</h1>
+<ul>
+	<li>Post 3</li>
+	<li>Post 2</li>
+</ul>
+<p>/posts/ | /posts/page/3/</p>
+";
+    assert!(contents == expected);
+
+    let mut file = fs::File::open(root.join("output/posts/page/3/index.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let expected = "<h1>Page 3 of 3</h1>
+<ul>
+	<li>Post 1</li>
+</ul>
+<p>/posts/page/2/ | none</p>
+";
+    assert!(contents == expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_taxonomies() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/taxonomies");
+    // Build site
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .status().is_ok());
+    // Check pages
+    assert!(root.join("output/tags/static-sites/index.html").is_file());
+    assert!(!root.join("output/topics/index.html").exists());
+    let mut file = fs::File::open(root.join("output/tags/index.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let expected = "<ul>
+	<li><a href=\"/tags/rust/\">Rust</a> (2)</li>
+	<li><a href=\"/tags/static-sites/\">Static Sites</a> (1)</li>
+</ul>
+";
+    assert!(contents == expected);
+
+    let mut file = fs::File::open(root.join("output/tags/rust/index.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let expected = "<h1>tags: Rust</h1>
+<a href=\"/hello.html\">Hello</a>
+<a href=\"/world.html\">World</a>
+";
+    assert!(contents == expected);
+
+    let mut file = fs::File::open(root.join("output/topics/news/index.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let expected = "<h1>categories: news</h1>
+<a href=\"/hello.html\">Hello</a>
+";
+    assert!(contents == expected);
+
+    let mut file = fs::File::open(root.join("output/index.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    assert!(contents == "<p>rust static-sites </p>\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_data() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/data");
+    // Build site
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .status().is_ok());
+    // Check pages
+    let mut file = fs::File::open(root.join("output/index.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let expected = "<h1>Terafirma (2023-07-01)</h1>
+Ada: Engineer
+Grace: Admiral
+0.2.0 Layouts
+0.1.0 First release
+CLI linux=yes windows=no
+Library linux=yes windows=yes
+";
+    assert!(contents == expected);
+    // Data files and directories with the same name are not merged
+    let output = Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .args(["--config", "Conflict.toml", "build"])
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.starts_with("Error: Data directory 'team' conflicts with a data file of the same name\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_generate() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/generate");
+    // Build site
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .status().is_ok());
+    // Check pages
+    assert!(root.join("output/team/grace.html").is_file());
+    assert!(root.join("output/products/terafirma-library.html").is_file());
+    let mut file = fs::File::open(root.join("output/team/ada.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    assert!(contents == "<h1>Ada</h1>\n<p>Core team</p>\n");
+
+    let mut file = fs::File::open(root.join("output/products/terafirma-cli.html"))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    assert!(contents == "<h1>Terafirma CLI</h1>\n<p>$0</p>\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_serve() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/serve");
+    // Start server
+    let mut child = Running(Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .args(["serve", "--port", "0", "--no-watch"])
+        .stdout(Stdio::piped())
+        .spawn()?);
+    let lines = read_lines(&mut child);
+    let addr = wait_for_line(&lines, "Serving: http://").unwrap()["Serving: http://".len()..].to_string();
+    // Check responses
+    let response = http_get(&addr, "/")?;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\n<p>Home</p>\n"));
+    let response = http_get(&addr, "/about")?;
+    assert!(response.ends_with("\r\n\r\n<p>About</p>\n"));
+    let response = http_get(&addr, "/docs")?;
+    assert!(response.ends_with("\r\n\r\n<p>Docs</p>\n"));
+    let response = http_get(&addr, "/docs/?page=1")?;
+    assert!(response.ends_with("\r\n\r\n<p>Docs</p>\n"));
+    let response = http_get(&addr, "/style.css")?;
+    assert!(response.contains("Content-Type: text/css"));
+    let response = http_get(&addr, "/missing")?;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    assert!(response.ends_with("\r\n\r\n<p>Missing</p>\n"));
+    let response = http_get(&addr, "/../Terafirma.toml")?;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    drop(child);
+
+    Ok(())
+}
+
+#[test]
+fn test_watch() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/watch");
+    fs::create_dir_all(root.join("pages"))?;
+    fs::create_dir_all(root.join("templates"))?;
+    fs::write(root.join("pages/index.html"), "First")?;
+    fs::write(root.join("templates/base.html"), "<main>{{ content | safe }}</main>")?;
+    // Start watching
+    let mut child = Running(Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .args(["build", "--watch"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?);
+    let lines = read_lines(&mut child);
+    assert!(wait_for_line(&lines, "Build: ").is_some());
+    assert!(fs::read_to_string(root.join("output/index.html"))? == "<main>First</main>");
+    // Change page
+    fs::write(root.join("pages/index.html"), "Second")?;
+    assert!(wait_for_line(&lines, "Build: ").is_some());
+    assert!(fs::read_to_string(root.join("output/index.html"))? == "<main>Second</main>");
+    // Break and fix template
+    fs::write(root.join("templates/base.html"), "<main>{{ content | safe </main>")?;
+    assert!(wait_for_line(&lines, "Error: ").is_some());
+    fs::write(root.join("templates/base.html"), "<div>{{ content | safe }}</div>")?;
+    assert!(wait_for_line(&lines, "Build: ").is_some());
+    assert!(fs::read_to_string(root.join("output/index.html"))? == "<div>Second</div>");
+    drop(child);
+
+    Ok(())
+}
+
+#[test]
+fn test_live_reload() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/live-reload");
+    fs::create_dir_all(root.join("pages"))?;
+    fs::create_dir_all(root.join("static"))?;
+    fs::write(root.join("pages/index.html"), "<html><body><p>Live</p></body></html>")?;
+    fs::write(root.join("static/style.css"), "p { color: red; }")?;
+    // Start server
+    let mut child = Running(Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .args(["serve", "--port", "0"])
+        .stdout(Stdio::piped())
+        .spawn()?);
+    let lines = read_lines(&mut child);
+    let addr = wait_for_line(&lines, "Serving: http://").unwrap()["Serving: http://".len()..].to_string();
+    assert!(wait_for_line(&lines, "Build: ").is_some());
+    // Check script is only added to served pages
+    let response = http_get(&addr, "/")?;
+    assert!(response.contains("<p>Live</p><script>"));
+    assert!(response.ends_with("</script></body></html>"));
+    assert!(fs::read_to_string(root.join("output/index.html"))? == "<html><body><p>Live</p></body></html>");
+    let response = http_get(&addr, "/style.css")?;
+    assert!(!response.contains("<script>"));
+    // Connect to reload events
+    let mut stream = TcpStream::connect(&addr)?;
+    stream.set_read_timeout(Some(Duration::from_secs(20)))?;
+    write!(stream, "GET /__terafirma/reload HTTP/1.1\r\nHost: {}\r\n\r\n", addr)?;
+    let mut events = BufReader::new(stream).lines();
+    assert!(events.next().unwrap()? == "HTTP/1.1 200 OK");
+    // Check events
+    fs::write(root.join("static/style.css"), "p { color: blue; }")?;
+    assert!(wait_for_line(&lines, "Build: ").is_some());
+    assert!(events.find(|l| l.as_ref().map_or(true, |l| l.starts_with("event:"))).unwrap()? == "event: css");
+    fs::write(root.join("pages/index.html"), "<html><body><p>Reloaded</p></body></html>")?;
+    assert!(wait_for_line(&lines, "Build: ").is_some());
+    assert!(events.find(|l| l.as_ref().map_or(true, |l| l.starts_with("event:"))).unwrap()? == "event: reload");
+    drop(child);
+
+    Ok(())
+}
+
+#[test]
+fn test_incremental() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/incremental");
+    let _ = fs::remove_dir_all(root.join("pages"));
+    fs::create_dir_all(root.join("pages"))?;
+    fs::create_dir_all(root.join("static"))?;
+    fs::create_dir_all(root.join("templates"))?;
+    fs::write(root.join("pages/a.html"), "A")?;
+    fs::write(root.join("pages/b.html"), "B")?;
+    fs::write(root.join("pages/list.html"), "{% include \"list.html\" %}")?;
+    fs::write(root.join("static/style.css"), "p { color: red; }")?;
+    fs::write(root.join("templates/base.html"), "<main>{{ content | safe }}</main>")?;
+    fs::write(root.join("templates/list.html"), "{% for p in site.pages %}{{ p.path }} {% endfor %}")?;
+    let build = |args: &[&str]| Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .arg("build")
+        .args(args)
+        .output();
+    assert!(build(&["--full"])?.status.success());
+    assert!(fs::read_to_string(root.join("output/list.html"))? == "<main>/a.html /b.html /list.html </main>");
+    // Mark outputs to find which are rewritten
+    for f in ["a.html", "b.html", "list.html", "style.css"] {
+        fs::write(root.join("output").join(f), "Unchanged")?;
+    }
+    // Only the changed page is rebuilt
+    fs::write(root.join("pages/b.html"), "Second B")?;
+    assert!(build(&[])?.status.success());
+    assert!(fs::read_to_string(root.join("output/a.html"))? == "Unchanged");
+    assert!(fs::read_to_string(root.join("output/b.html"))? == "<main>Second B</main>");
+    assert!(fs::read_to_string(root.join("output/list.html"))? == "Unchanged");
+    assert!(fs::read_to_string(root.join("output/style.css"))? == "Unchanged");
+    // Pages using the site are rebuilt when pages are added or removed
+    fs::write(root.join("pages/c.html"), "C")?;
+    fs::remove_file(root.join("pages/b.html"))?;
+    assert!(build(&[])?.status.success());
+    assert!(fs::read_to_string(root.join("output/a.html"))? == "Unchanged");
+    assert!(!root.join("output/b.html").exists());
+    assert!(fs::read_to_string(root.join("output/c.html"))? == "<main>C</main>");
+    assert!(fs::read_to_string(root.join("output/list.html"))? == "<main>/a.html /c.html /list.html </main>");
+    // Changed templates and static files
+    fs::write(root.join("templates/base.html"), "<div>{{ content | safe }}</div>")?;
+    fs::write(root.join("static/style.css"), "p { color: blue; }")?;
+    assert!(build(&[])?.status.success());
+    assert!(fs::read_to_string(root.join("output/a.html"))? == "<div>A</div>");
+    assert!(fs::read_to_string(root.join("output/style.css"))? == "p { color: blue; }");
+    // Full builds rebuild everything
+    fs::write(root.join("output/a.html"), "Unchanged")?;
+    assert!(build(&["--full"])?.status.success());
+    assert!(fs::read_to_string(root.join("output/a.html"))? == "<div>A</div>");
+
+    Ok(())
+}
+
+#[test]
+fn test_parallel() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/parallel");
+    // Build site
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .args(["--jobs", "4", "build", "--full"])
+            .status().is_ok());
+    // Check pages and static files
+    for i in 1..=40 {
+        let contents = fs::read_to_string(root.join(format!("output/items/{}.html", i)))?;
+        assert!(contents == format!("<p>Item {}</p>\n", i));
+    }
+    assert!(fs::read_to_string(root.join("output/js/main.js"))? == "console.log(1);\n");
+    // The first failing page is always reported
+    for _ in 0..5 {
+        let output = Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .env("RUST_BACKTRACE", "0")
+            .args(["--config", "Strict.toml", "--jobs", "8", "build", "--full"])
+            .output()?;
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr)?;
+        assert!(stderr.starts_with("Error: Failed to build page '/items/12.html' (from 'templates/item.html')\n"));
+        assert!(!stderr.contains("/items/31.html"));
+    }
+    // Every failing page is reported in order
+    let output = Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .env("RUST_BACKTRACE", "0")
+        .args(["--config", "Strict.toml", "build", "--keep-going"])
+        .output()?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr)?;
+    let errors: Vec<&str> = stderr.lines().filter(|l| l.starts_with("Error: ")).collect();
+    assert!(errors == ["Error: Failed to build page '/items/12.html' (from 'templates/item.html')",
+        "Error: Failed to build page '/items/31.html' (from 'templates/item.html')",
+        "Error: Failed to build 2 pages"]);
+    assert!(stderr.contains("  Caused by: Variable `item.missing.value` not found in context while rendering 'item.html'\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_atomic() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/atomic");
+    fs::create_dir_all(root.join("pages"))?;
+    fs::create_dir_all(root.join("templates"))?;
+    fs::write(root.join("pages/a.html"), "A")?;
+    fs::write(root.join("pages/b.html"), "B")?;
+    fs::write(root.join("templates/base.html"), "<main>{{ content | safe }}</main>")?;
+    let build = || Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .arg("build")
+        .output();
+    assert!(build()?.status.success());
+    assert!(fs::read_to_string(root.join("output/a.html"))? == "<main>A</main>");
+    // A failed build leaves the previous output untouched
+    fs::write(root.join("pages/a.html"), "Second A")?;
+    fs::write(root.join("pages/b.html"), "{{ missing }}")?;
+    let output = build()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.starts_with("Error: "));
+    assert!(fs::read_to_string(root.join("output/a.html"))? == "<main>A</main>");
+    assert!(fs::read_to_string(root.join("output/b.html"))? == "<main>B</main>");
+    assert!(!root.join(".output.staging").exists());
+    // Output left aside by an interrupted swap is restored, even when the next build fails
+    fs::rename(root.join("output"), root.join(".output.old"))?;
+    assert!(!build()?.status.success());
+    assert!(fs::read_to_string(root.join("output/a.html"))? == "<main>A</main>");
+    assert!(!root.join(".output.old").exists());
+    // Fixed builds replace it
+    fs::write(root.join("pages/b.html"), "Second B")?;
+    assert!(build()?.status.success());
+    assert!(fs::read_to_string(root.join("output/a.html"))? == "<main>Second A</main>");
+    assert!(fs::read_to_string(root.join("output/b.html"))? == "<main>Second B</main>");
+    assert!(!root.join(".output.staging").exists());
+    assert!(!root.join(".output.old").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_messages() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/messages");
+    let build = |args: &[&str]| Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .args(args)
+        .output();
+    // Summary
+    let output = build(&["build", "--full"])?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.starts_with("Build: rendered 2 pages, copied 1 static file and wrote 1 redirect in "));
+    // Every file written
+    let output = build(&["--verbose", "build", "--full"])?;
+    let stdout = String::from_utf8(output.stdout)?;
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines[..4] == ["  Rendered /about.html", "  Rendered /index.html", "  Copied /style.css", "  Redirected /old.html"]);
+    // Nothing but errors
+    let output = build(&["--quiet", "build"])?;
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let output = build(&["--quiet", "--config", "Missing.toml"])?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.starts_with("Error: "));
+    // JSON
+    let output = build(&["--message-format", "json", "build", "--full"])?;
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.starts_with("{\"elapsed\":"));
+    assert!(stdout.ends_with(",\"pages\":2,\"redirects\":1,\"removed\":0,\"static_files\":1,\"type\":\"build\",\"unchanged\":0}\n"));
+    let output = build(&["--message-format", "json", "--config", "Missing.toml"])?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stdout)?.starts_with("{\"causes\":[],\"message\":"));
+
+    Ok(())
+}
+
+#[test]
+fn test_collisions() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/collisions");
+    let build = |config: &str| Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .args(["--config", config])
+        .output();
+    // Both sources are named
+    let output = build("static-page.toml")?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.starts_with(
+        "Error: Output '/foo.html' is produced by both static file 'static/foo.html' and [[page]] in 'static-page.toml'\n"));
+    let output = build("redirect-page.toml")?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.starts_with(
+        "Error: Output '/bar.html' is produced by both [[redirect]] in 'redirect-page.toml' and page file 'pages/bar.html'\n"));
+    let output = build("body-page.toml")?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.starts_with(
+        "Error: Output '/bar.html' is produced by both [[page]] in 'body-page.toml' and page file 'pages/bar.html'\n"));
+    let output = build("files.toml")?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.starts_with(
+        "Error: Output '/baz.html' is produced by both page file 'files/baz.html' and page file 'files/baz.md'\n"));
+    // Pages can replace static files when allowed
+    assert!(build("override.toml")?.status.success());
+    assert!(fs::read_to_string(root.join("output/foo.html"))? == "Page foo");
+    assert!(fs::read_to_string(root.join("output/bar.html"))? == "Page bar\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_sitemap() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/sitemap");
+    // Build site
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .status()?.success());
+    // Check sitemap
+    let contents = fs::read_to_string(root.join("output/sitemap.xml"))?;
+    let expected = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">
+	<url>
+		<loc>https://example.com/docs/</loc>
+		<changefreq>daily</changefreq>
+		<priority>1</priority>
+	</url>
+	<url>
+		<loc>https://example.com/docs/posts/first.html</loc>
+		<lastmod>2023-06-01</lastmod>
+	</url>
+	<url>
+		<loc>https://example.com/docs/posts/second.html</loc>
+		<lastmod>2023-07-04</lastmod>
+		<priority>0.5</priority>
+	</url>
+	<url>
+		<loc>https://example.com/docs/posts/third.html</loc>
+		<lastmod>2023-08-15T10:00:00+02:00</lastmod>
+	</url>
+</urlset>
+";
+    assert!(contents == expected);
+    // Dates which are not W3C datetimes are rejected
+    let output = Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .args(["--config", "Invalid.toml", "build"])
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.starts_with(
+        "Error: Invalid sitemap lastmod 'June 2023' for page '/index.html', it must be a date such as '2023-06-01'\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_feeds() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/feeds");
+    // Build site
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .status()?.success());
+    // Check section feeds, newest two posts
+    let contents = fs::read_to_string(root.join("output/posts/rss.xml"))?;
+    let expected = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">
+<channel>
+	<title>Posts</title>
+	<link>https://example.com/blog/</link>
+	<description>Latest posts</description>
+	<atom:link href=\"https://example.com/blog/posts/rss.xml\" rel=\"self\" type=\"application/rss+xml\"/>
+	<lastBuildDate>Tue, 15 Aug 2023 00:00:00 +0000</lastBuildDate>
+	<item>
+		<title>Third &lt;post&gt;</title>
+		<link>https://example.com/blog/posts/third.html</link>
+		<guid>https://example.com/blog/posts/third.html</guid>
+		<pubDate>Tue, 15 Aug 2023 00:00:00 +0000</pubDate>
+		<description>Tags &amp; such</description>
+	</item>
+	<item>
+		<title>Second post</title>
+		<link>https://example.com/blog/posts/second.html</link>
+		<guid>https://example.com/blog/posts/second.html</guid>
+		<pubDate>Tue, 4 Jul 2023 12:30:00 +0000</pubDate>
+	</item>
+</channel>
+</rss>
+";
+    assert!(contents == expected);
+    let contents = fs::read_to_string(root.join("output/posts/atom.xml"))?;
+    assert!(contents.contains("\t<updated>2023-08-15T00:00:00Z</updated>\n"));
+    assert!(contents.contains("\t\t<link href=\"https://example.com/blog/posts/second.html\"/>\n"));
+    assert!(contents.matches("<entry>").count() == 2);
+    assert!(contents.contains("\t<author>\n\t\t<name>Penn &amp; co</name>\n\t</author>\n"));
+    let feed: serde_json::Value = serde_json::from_str(&fs::read_to_string(root.join("output/posts/feed.json"))?)?;
+    assert!(feed["version"] == "https://jsonfeed.org/version/1.1");
+    assert!(feed["feed_url"] == "https://example.com/blog/posts/feed.json");
+    assert!(feed["items"][0]["summary"] == "Tags & such");
+    assert!(feed["items"][0]["content_text"] == "Tags & such");
+    assert!(feed["items"][1]["content_text"] == "Second post");
+    assert!(feed["authors"][0]["name"] == "Penn & co");
+    assert!(feed["items"][1]["date_published"] == "2023-07-04T12:30:00Z");
+    // Check pattern feed, every post sorted by title
+    let feed: serde_json::Value = serde_json::from_str(&fs::read_to_string(root.join("output/archive.json"))?)?;
+    let urls: Vec<&str> = feed["items"].as_array().unwrap().iter()
+        .map(|i| i["url"].as_str().unwrap())
+        .collect();
+    assert!(urls == [
+        "https://example.com/blog/posts/first.html",
+        "https://example.com/blog/posts/2023/old.html",
+        "https://example.com/blog/posts/second.html",
+        "https://example.com/blog/posts/third.html",
+    ]);
+    // Dates are sorted as times, so the limit keeps the newest page whatever its time zone
+    let feed: serde_json::Value = serde_json::from_str(&fs::read_to_string(root.join("output/zones.json"))?)?;
+    assert!(feed["items"].as_array().unwrap().len() == 1);
+    assert!(feed["items"][0]["url"] == "https://example.com/blog/zones/utc.html");
+    // Atom feeds without dates are updated at the Unix epoch, not the time they were built
+    let contents = fs::read_to_string(root.join("output/undated.xml"))?;
+    assert!(contents.contains("\t<updated>1970-01-01T00:00:00Z</updated>\n"));
+    assert!(contents.contains("\t\t<updated>1970-01-01T00:00:00Z</updated>\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_urls() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/urls");
+    // Build site hosted in a subdirectory
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .status()?.success());
+    let contents = fs::read_to_string(root.join("output/index.html"))?;
+    assert!(contents == "<a href=\"/docs/posts/\">https://example.com/docs/posts/first.html</a> https://example.com/docs/\n");
+    let contents = fs::read_to_string(root.join("output/posts/index.html"))?;
+    assert!(contents == "<a href=\"/docs/\">Home</a>\n");
+    // Override base URL, pages are rebuilt even though their sources are unchanged
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .args(["--base-url", "http://localhost:8000"])
+            .status()?.success());
+    let contents = fs::read_to_string(root.join("output/index.html"))?;
+    assert!(contents == "<a href=\"/posts/\">http://localhost:8000/posts/first.html</a> http://localhost:8000\n");
+    let contents = fs::read_to_string(root.join("output/posts/index.html"))?;
+    assert!(contents == "<a href=\"/\">Home</a>\n");
+    // Base URL is only required for sitemaps and feeds, so it can come from the command line
+    let output = Command::new(get_crate_exe().unwrap())
+        .current_dir(env::current_dir()?.join("tests/invalid"))
+        .args(["--config", "feed-base-url.toml", "--base-url", "https://example.com/", "check"])
+        .output()?;
+    assert!(output.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_functions() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/functions");
+    // Build site
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .args(["build", "--full"])
+            .env("TERAFIRMA_STAGE", "prod")
+            .status()?.success());
+    let contents = fs::read_to_string(root.join("output/index.html"))?;
+    assert!(contents == "First Post, 1 post, Terafirma, true false\n");
+    assert!(fs::read_to_string(root.join("output/env.html"))? == "prod\n");
+    // Pages calling load_data(), now() or get_env() are rendered by every build
+    let output = Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .arg("--verbose")
+        .env("TERAFIRMA_STAGE", "staging")
+        .output()?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.starts_with("  Rendered /env.html\n  Rendered /index.html\n  Rendered /stamp.html\nBuild: rendered 3 pages"));
+    assert!(fs::read_to_string(root.join("output/env.html"))? == "staging\n");
+    // Paths outside the project are rejected
+    fs::write(root.join("pages/escape.html"), "{{ load_data(path=\"../invalid/page-path.toml\") }}")?;
+    let output = Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .output()?;
+    fs::remove_file(root.join("pages/escape.html"))?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("Invalid path '../invalid/page-path.toml' for the `load_data` function"));
+
+    Ok(())
+}
+
+#[test]
+fn test_shared_context() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/shared-context");
+    // Pages are rendered with one shared context per thread, so one thread checks values do not leak between pages
+    assert!(Command::new(get_crate_exe().unwrap())
+            .current_dir(&root)
+            .args(["--jobs", "1", "build", "--full"])
+            .status()?.success());
+    assert!(fs::read_to_string(root.join("output/a.html"))? == "A x /a.html\n");
+    assert!(fs::read_to_string(root.join("output/b.html"))? == "Site none /b.html\n");
+
+    Ok(())
+}
+
+// Benchmark, since timing depends on the machine's load, run with `cargo test -- --ignored`
+#[test]
+#[ignore]
+fn test_scaling() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/scaling");
+    // Build time should grow with the number of pages, not its square, even though every page sees the whole site
+    let mut times = vec![];
+    for count in [300, 1200] {
+        let _ = fs::remove_dir_all(root.join("pages"));
+        fs::create_dir_all(root.join("pages"))?;
+        for i in 0..count {
+            fs::write(root.join(format!("pages/page-{}.html", i)),
+                format!("+++\ntitle = \"Page {}\"\n+++\n{{{{ title }}}} of {{{{ site.pages | length }}}}\n", i))?;
+        }
+        let start = Instant::now();
+        assert!(Command::new(get_crate_exe().unwrap())
+                .current_dir(&root)
+                .args(["build", "--full"])
+                .status()?.success());
+        times.push(start.elapsed());
+    }
+    let contents = fs::read_to_string(root.join("output/page-7.html"))?;
+    assert!(contents == "Page 7 of 1200\n");
+    // Four times the pages, allowing for timing noise
+    assert!(times[1] < times[0] * 8, "{:?} pages took {:?}", [300, 1200], times);
+
+    Ok(())
+}
+
+#[test]
+fn test_error_order() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/error-order");
+    // The first error is the first failing page by path, not configuration or directory listing order,
+    // including page files which cannot be read
+    let output = Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .args(["build", "--full"])
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.starts_with("Error: Failed to build page '/a.html' (from 'pages/a.html')\n"));
+    let output = Command::new(get_crate_exe().unwrap())
+        .current_dir(&root)
+        .args(["build", "--full", "--keep-going"])
+        .output()?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr)?;
+    let errors: Vec<&str> = stderr.lines().filter(|l| l.starts_with("Error: ")).collect();
+    assert!(errors == ["Error: Failed to build page '/a.html' (from 'pages/a.html')",
+        "Error: Failed to build page '/m/b.html' (from 'pages/m/b.html')",
+        "Error: Failed to build page '/u.html' (from 'pages/u.html')",
+        "Error: Failed to build page '/z.html' (from 'Terafirma.toml')",
+        "Error: Failed to build 4 pages"]);
+    assert!(stderr.contains("  Caused by: Failed to read front matter of page file 'u.html'\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_nested_output() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/nested-output");
+    // Output directories inside source directories would be copied or rendered back into the next build
+    for (config, name, dir) in [("Terafirma.toml", "static", "static"), ("Pages.toml", "page", "pages"), ("Root.toml", "static", ".")] {
+        for command in ["build", "check"] {
+            let output = Command::new(get_crate_exe().unwrap())
+                .current_dir(&root)
+                .args(["--config", config, command])
+                .output()?;
+            assert!(!output.status.success(), "{} should fail {}", config, command);
+            let expected = format!("is inside the {} directory '{}', so builds would read their own output\n",
+                name, root.join(dir).display());
+            assert!(String::from_utf8(output.stderr)?.ends_with(&expected));
+        }
+    }
+    assert!(!root.join("static/out").exists());
+    assert!(!root.join("static/.out.staging").exists());
+    assert!(!root.join("pages/out").exists());
+
+    Ok(())
+}
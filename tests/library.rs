@@ -0,0 +1,45 @@
+// Terafirma library tests
+//   Penn Bauman <me@pennbauman.com>
+use std::collections::HashMap;
+use std::fs;
+use std::env;
+use terafirma::SiteBuilder;
+use terafirma::tera::{self, Value};
+
+
+#[test]
+fn test_extensions() -> Result<(), Box<dyn std::error::Error>> {
+    let root = env::current_dir()?.join("tests/library");
+    let mut builder = SiteBuilder::from_file(root.join("Terafirma.toml"))?;
+    builder.register_filter("shout", |value: &Value, _: &HashMap<String, Value>| -> tera::Result<Value> {
+        Ok(Value::String(value.as_str().unwrap_or_default().to_uppercase()))
+    });
+    builder.register_function("greeting", |args: &HashMap<String, Value>| -> tera::Result<Value> {
+        Ok(Value::String(format!("Hi {}", args["name"].as_str().unwrap_or_default())))
+    });
+    builder.register_tester("multiple_of", |value: Option<&Value>, args: &[Value]| -> tera::Result<bool> {
+        Ok(value.and_then(|v| v.as_u64()).unwrap_or(1).is_multiple_of(args[0].as_u64().unwrap_or(1)))
+    });
+    // The layout template only exists once the hook adds it
+    assert!(builder.check().is_err());
+    builder.configure_tera(|tera| {
+        tera.add_raw_template("wrap.html", "<main>{{ content | safe }}</main>\n")?;
+        Ok(())
+    });
+    assert!(format!("{:?}", builder).contains("filters: [\"shout\"]"));
+    builder.check()?;
+    // Build site
+    builder.build()?;
+    let contents = fs::read_to_string(root.join("output/index.html"))?;
+    assert!(contents == "<main>HELLO Hi Penn fizz\n</main>\n");
+    // Changed extensions are used even though no files changed, since pages are not reused
+    builder.register_filter("shout", |value: &Value, _: &HashMap<String, Value>| -> tera::Result<Value> {
+        Ok(Value::String(value.as_str().unwrap_or_default().to_uppercase() + "!"))
+    });
+    builder.build()?;
+    let contents = fs::read_to_string(root.join("output/index.html"))?;
+    assert!(contents == "<main>HELLO! Hi Penn fizz\n</main>\n");
+    assert!(!root.join(".terafirma-cache.json").exists());
+
+    Ok(())
+}